@@ -0,0 +1,187 @@
+//! Finding bidirectional control characters, which can make text display differently to how it is stored.
+//!
+//! Overrides like U+202E RIGHT-TO-LEFT OVERRIDE are invisible, but reverse the text that follows them.
+//! In source code, this can hide what a line really does (see [Trojan Source](https://trojansource.codes/)).
+//! ```
+//! let bytes = "abc\u{202E}def".as_bytes();
+//! let string = string_inspector::DecodedString::decode(bytes, encoding::all::UTF_8).unwrap();
+//! let lines = string_inspector::bidi::analyse(&string);
+//!
+//! assert_eq!(vec![3], lines[0].unterminated);
+//! assert_eq!("abc\u{202E}fed", lines[0].visual);
+//! ```
+use unicode_bidi::BidiInfo;
+use crate::decoding::{Atom, DecodedString};
+
+/// A line containing bidirectional control characters.
+pub struct Line {
+    /// The line number, starting from 1.
+    pub number: usize,
+    /// The indexes of the atoms that are bidirectional control characters.
+    pub controls: Vec<usize>,
+    /// The indexes of controls that start an embedding, override or isolate that is still open at the end of the line.
+    pub unterminated: Vec<usize>,
+    /// The text of the line in the order it is stored.
+    pub logical: String,
+    /// The text of the line in the order it is displayed.
+    pub visual: String
+}
+
+/// What a control character starts, so we know which character ends it.
+#[derive(PartialEq)]
+enum Opening {
+    /// Ended by U+202C POP DIRECTIONAL FORMATTING.
+    Embedding,
+    /// Ended by U+2069 POP DIRECTIONAL ISOLATE.
+    Isolate
+}
+
+/// The short name of a bidirectional control character, or `None` for any other character.
+pub fn abbreviation(character: char) -> Option<&'static str> {
+    let abbreviation = match character {
+        '\u{061C}' => "ALM",
+        '\u{200E}' => "LRM",
+        '\u{200F}' => "RLM",
+        '\u{202A}' => "LRE",
+        '\u{202B}' => "RLE",
+        '\u{202C}' => "PDF",
+        '\u{202D}' => "LRO",
+        '\u{202E}' => "RLO",
+        '\u{2066}' => "LRI",
+        '\u{2067}' => "RLI",
+        '\u{2068}' => "FSI",
+        '\u{2069}' => "PDI",
+        _ => return None
+    };
+    Some(abbreviation)
+}
+
+/// Replace bidirectional control characters with visible markers like `⟨RLO⟩`.
+pub fn mark_controls(text: &str) -> String {
+    text.chars().map(|character| match abbreviation(character) {
+        Some(abbreviation) => format!("⟨{}⟩", abbreviation),
+        None => character.to_string()
+    }).collect()
+}
+
+/// Find every line that contains bidirectional control characters.
+///
+/// Lines are separated by paragraph separators, which end any embeddings, overrides and isolates.
+pub fn analyse(decoding: &DecodedString) -> Vec<Line> {
+    split_lines(&decoding.atoms).into_iter()
+        .enumerate()
+        .filter_map(|(number, atoms)| analyse_line(number + 1, atoms))
+        .collect()
+}
+
+fn analyse_line(number: usize, atoms: &[Atom]) -> Option<Line> {
+    let controls: Vec<usize> = atoms.iter()
+        .filter(|atom| abbreviation(atom.to_char()).is_some())
+        .map(Atom::index)
+        .collect();
+
+    if controls.is_empty() {
+        return None;
+    }
+
+    let mut open: Vec<(usize, Opening)> = Vec::new();
+    for atom in atoms.iter() {
+        match atom.to_char() {
+            '\u{202A}' | '\u{202B}' | '\u{202D}' | '\u{202E}' => open.push((atom.index(), Opening::Embedding)),
+            '\u{2066}' | '\u{2067}' | '\u{2068}' => open.push((atom.index(), Opening::Isolate)),
+            // An embedding can't be closed from inside an isolate
+            '\u{202C}' if open.last().is_some_and(|(_, opening)| *opening == Opening::Embedding) => {
+                open.pop();
+            }
+            '\u{2069}' => {
+                // Closing an isolate also closes any embeddings inside it
+                if let Some(position) = open.iter().rposition(|(_, opening)| *opening == Opening::Isolate) {
+                    open.truncate(position);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let logical: String = atoms.iter().map(Atom::to_char).collect();
+    let bidi_info = BidiInfo::new(&logical, None);
+    let visual: String = bidi_info.paragraphs.iter()
+        .map(|paragraph| bidi_info.reorder_line(paragraph, paragraph.range.clone()))
+        .collect();
+
+    Some(Line {
+        number,
+        controls,
+        unterminated: open.into_iter().map(|(index, _)| index).collect(),
+        logical,
+        visual
+    })
+}
+
+/// Split atoms into lines, without the paragraph separators at the end of each line.
+fn split_lines(atoms: &[Atom]) -> Vec<&[Atom]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+
+    for (position, atom) in atoms.iter().enumerate() {
+        let is_separator = match atom.to_char() {
+            // CRLF is a single line break
+            '\r' => atoms.get(position + 1).map(Atom::to_char) != Some('\n'),
+            '\n' | '\u{1C}'..='\u{1E}' | '\u{85}' | '\u{2029}' => true,
+            _ => false
+        };
+
+        if is_separator {
+            let end = if position > start && atom.to_char() == '\n' && atoms[position - 1].to_char() == '\r' { position - 1 } else { position };
+            lines.push(&atoms[start..end]);
+            start = position + 1;
+        }
+    }
+
+    lines.push(&atoms[start..]);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::UTF_8;
+
+    fn analyse_text(text: &str) -> Vec<Line> {
+        let decoding = DecodedString::decode(text.as_bytes(), UTF_8).unwrap();
+        analyse(&decoding)
+    }
+
+    #[test]
+    fn text_without_controls() {
+        assert!(analyse_text("hello\nworld").is_empty());
+    }
+
+    #[test]
+    fn terminated_controls_on_each_line() {
+        let lines = analyse_text("one\r\ntwo \u{2067}three\u{2069}\nfour \u{202E}\u{2066}x\u{2069}y\u{202C}");
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0].number, 2);
+        assert_eq!(lines[0].controls, vec![9, 15]);
+        assert!(lines[0].unterminated.is_empty());
+
+        assert_eq!(lines[1].number, 3);
+        assert!(lines[1].unterminated.is_empty());
+    }
+
+    #[test]
+    fn unterminated_controls_end_with_the_line() {
+        let lines = analyse_text("a\u{2066}\u{202E}b\u{2069}\n\u{202C}");
+        assert!(lines[0].unterminated.is_empty());
+        assert!(lines[1].unterminated.is_empty());
+
+        let lines = analyse_text("a\u{2066}b\u{202C}\nc");
+        assert_eq!(lines[0].unterminated, vec![1]);
+    }
+
+    #[test]
+    fn markers_for_controls() {
+        assert_eq!(mark_controls("a\u{202E}b\u{202C}"), "a⟨RLO⟩b⟨PDF⟩");
+    }
+}
@@ -0,0 +1,158 @@
+//! Breaking characters down into the bits of their encoded form.
+//!
+//! Each code unit is split into marker bits, which are fixed by the encoding and say what role the
+//! code unit plays, and payload bits, which are joined together to make the code point.
+//! UTF-32 code units also have unused bits at the top, because code points only need 21 bits.
+//! ```
+//! let string = string_inspector::DecodedString::decode("é".as_bytes(), encoding::all::UTF_8).unwrap();
+//! let breakdown = match &string.atoms[0] {
+//!     string_inspector::Atom::Character(c) => string_inspector::bits::breakdown(c, string.encoding).unwrap(),
+//!     _ => unreachable!()
+//! };
+//!
+//! assert_eq!("110", breakdown.units[0].marker);
+//! assert_eq!("00011", breakdown.units[0].payload);
+//! assert_eq!("10", breakdown.units[1].marker);
+//! assert_eq!("101001", breakdown.units[1].payload);
+//! assert_eq!(0xe9, breakdown.code_point);
+//! ```
+use encoding::types::Encoding;
+use crate::decoding::{CodeUnit, DecodedCharacter, EncodingForm};
+
+/// One code unit of an encoded character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitBits {
+    /// The bytes of the code unit, in the order they appear in the input.
+    pub bytes: Vec<u8>,
+    /// High bits that are always zero, because the code unit is bigger than any code point needs.
+    /// These are only in UTF-32.
+    pub unused: String,
+    /// The bits that are fixed by the encoding, such as the 10 that starts a UTF-8 continuation byte.
+    pub marker: String,
+    /// The bits that carry part of the code point.
+    pub payload: String
+}
+
+/// The bits of every code unit of a character, and how they make up its code point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakdown {
+    pub units: Vec<UnitBits>,
+    /// Added to the joined payload bits to get the code point. This is 0x10000 for UTF-16 surrogate pairs,
+    /// which only encode code points above U+FFFF, and 0 otherwise.
+    pub offset: u32,
+    pub code_point: u32
+}
+
+impl Breakdown {
+    /// The payload bits of every code unit joined together, which give the code point after adding the offset.
+    pub fn payload(&self) -> String {
+        self.units.iter().map(|unit| unit.payload.as_str()).collect()
+    }
+}
+
+/// Write the lowest `count` bits of a number, most significant first.
+fn binary(value: u32, count: usize) -> String {
+    (0..count).rev().map(|bit| if value >> bit & 1 == 1 { '1' } else { '0' }).collect()
+}
+
+/// Split a code unit into its first `marker_size` bits and the rest.
+fn split(bytes: &[u8], value: u32, size: usize, marker_size: usize) -> UnitBits {
+    let bits = binary(value, size);
+    UnitBits { bytes: bytes.to_vec(), unused: String::new(), marker: bits[..marker_size].to_owned(), payload: bits[marker_size..].to_owned() }
+}
+
+/// Break a character down into bits, if it was decoded from UTF-8, UTF-16 or UTF-32.
+///
+/// Returns `None` for other encodings, or if the character's bytes are not its usual encoding,
+/// because the decoder attached extra bytes to it.
+pub fn breakdown(character: &DecodedCharacter, encoding: &dyn Encoding) -> Option<Breakdown> {
+    let code_point = character.character as u32;
+    let bytes = &character.bytes;
+
+    let (units, offset) = match EncodingForm::of(encoding)? {
+        EncodingForm::Utf8 if bytes.len() == character.character.len_utf8() => {
+            let units = bytes.iter().enumerate().map(|(index, &byte)| {
+                let marker_size = match (index, bytes.len()) {
+                    (0, 1) => 1,
+                    (0, length) => length + 1,
+                    _ => 2
+                };
+                split(&[byte], u32::from(byte), 8, marker_size)
+            }).collect();
+            (units, 0)
+        }
+        EncodingForm::Utf16(endianness) if bytes.len() == character.character.len_utf16() * 2 => {
+            let units: Vec<UnitBits> = bytes.chunks(2).map(|pair| {
+                // Surrogates start with 110110 (high) or 110111 (low), followed by 10 bits of payload
+                let marker_size = if bytes.len() == 4 { 6 } else { 0 };
+                split(pair, CodeUnit::from_bytes(pair, endianness).value(), 16, marker_size)
+            }).collect();
+            let offset = if units.len() == 2 { 0x10000 } else { 0 };
+            (units, offset)
+        }
+        EncodingForm::Utf32(endianness) if bytes.len() == 4 => {
+            // Code points only need 21 bits, so the top 11 are always zero. There are no marker bits.
+            let bits = binary(CodeUnit::from_bytes(bytes, endianness).value(), 32);
+            (vec![UnitBits { bytes: bytes.to_vec(), unused: bits[..11].to_owned(), marker: String::new(), payload: bits[11..].to_owned() }], 0)
+        }
+        _ => return None
+    };
+
+    Some(Breakdown { units, offset, code_point })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+    use crate::decoding::{Atom, DecodedString};
+    use crate::utf_32::UTF_32LE;
+
+    fn breakdowns(text: &str, encoding: encoding::types::EncodingRef) -> Vec<Option<Breakdown>> {
+        let bytes = encoding.encode(text, encoding::EncoderTrap::Strict).unwrap();
+        let decoding = DecodedString::decode(&bytes, encoding).unwrap();
+        decoding.atoms.iter().map(|atom| match atom {
+            Atom::Character(c) => breakdown(c, encoding),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn utf8_markers_depend_on_the_length() {
+        let breakdown = breakdowns("A💩", UTF_8);
+        let ascii = breakdown[0].as_ref().unwrap();
+        assert_eq!((ascii.units[0].marker.as_str(), ascii.units[0].payload.as_str()), ("0", "1000001"));
+
+        let emoji = breakdown[1].as_ref().unwrap();
+        let markers: Vec<&str> = emoji.units.iter().map(|unit| unit.marker.as_str()).collect();
+        assert_eq!(markers, vec!["11110", "10", "10", "10"]);
+        assert_eq!(u32::from_str_radix(&emoji.payload(), 2).unwrap(), 0x1f4a9);
+    }
+
+    #[test]
+    fn utf16_surrogate_pairs() {
+        let breakdown = breakdowns("💩", UTF_16LE)[0].clone().unwrap();
+        assert_eq!(breakdown.units[0].bytes, vec![0x3d, 0xd8]);
+        assert_eq!(breakdown.units[0].marker, "110110");
+        assert_eq!(breakdown.units[1].marker, "110111");
+        assert_eq!(u32::from_str_radix(&breakdown.payload(), 2).unwrap() + breakdown.offset, 0x1f4a9);
+
+        let bmp = breakdowns("é", UTF_16BE)[0].clone().unwrap();
+        assert_eq!((bmp.units[0].marker.as_str(), bmp.offset), ("", 0));
+        assert_eq!(bmp.units[0].payload, "0000000011101001");
+    }
+
+    #[test]
+    fn utf32_has_eleven_unused_bits() {
+        let breakdown = breakdowns("é", UTF_32LE)[0].clone().unwrap();
+        assert_eq!(breakdown.units[0].bytes, vec![0xe9, 0x00, 0x00, 0x00]);
+        assert_eq!(breakdown.units[0].unused, "00000000000");
+        assert_eq!(breakdown.units[0].marker, "");
+        assert_eq!(breakdown.units[0].payload, "000000000000011101001");
+    }
+
+    #[test]
+    fn other_encodings_have_no_breakdown() {
+        assert_eq!(breakdowns("é", WINDOWS_1252), vec![None]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Detecting byte order marks (BOMs).
+//!
+//! A byte order mark is the character U+FEFF at the very start of some text.
+//! Its encoded form identifies which unicode encoding the text uses:
+//! ```
+//! let bytes = [0xff, 0xfe, 0x41, 0x00];
+//! let (encoding, length) = string_inspector::bom::sniff(&bytes).unwrap();
+//!
+//! assert_eq!("utf-16le", encoding.name());
+//! assert_eq!(2, length);
+//! ```
+use std::fmt;
+use encoding::all::{UTF_8, UTF_16BE, UTF_16LE};
+use encoding::types::EncodingRef;
+use crate::decoding::{Atom, DecodedString, EncodingForm, Endianness};
+use crate::utf_32::{UTF_32BE, UTF_32LE};
+
+/// The byte order mark character.
+pub const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
+/// The UTF-32LE byte order mark, which starts with the UTF-16LE one.
+const UTF_32LE_MARK: [u8; 4] = [0xff, 0xfe, 0x00, 0x00];
+
+/// Work out the encoding of some bytes from their byte order mark.
+///
+/// Returns the encoding and the length of the byte order mark in bytes,
+/// or `None` if the bytes don't start with a byte order mark.
+///
+/// FF FE 00 00 is either a UTF-32LE byte order mark, or a UTF-16LE one followed by U+0000.
+/// It is only taken to be UTF-32LE if the rest of the bytes are valid UTF-32LE.
+pub fn sniff(bytes: &[u8]) -> Option<(EncodingRef, usize)> {
+    if bytes.starts_with(&UTF_32LE_MARK) && is_utf32le(bytes[UTF_32LE_MARK.len()..].iter().copied()) {
+        return Some((UTF_32LE, UTF_32LE_MARK.len()));
+    }
+
+    let marks: [(&[u8], EncodingRef); 4] = [
+        (&[0x00, 0x00, 0xfe, 0xff], UTF_32BE),
+        (&[0xef, 0xbb, 0xbf], UTF_8),
+        (&[0xff, 0xfe], UTF_16LE),
+        (&[0xfe, 0xff], UTF_16BE),
+    ];
+
+    marks.iter()
+        .find(|(mark, _)| bytes.starts_with(mark))
+        .map(|&(mark, encoding)| (encoding, mark.len()))
+}
+
+/// Whether some bytes are whole UTF-32LE code units, each of which is a valid code point.
+fn is_utf32le<I: IntoIterator<Item = u8>>(bytes: I) -> bool {
+    let mut unit = [0; 4];
+    let mut length = 0;
+    for byte in bytes {
+        unit[length] = byte;
+        length += 1;
+        if length == unit.len() {
+            if char::from_u32(u32::from_le_bytes(unit)).is_none() {
+                return false;
+            }
+            length = 0;
+        }
+    }
+    length == 0
+}
+
+/// Something suspicious about the byte order marks in a decoded string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The input starts with the byte order mark of a different encoding.
+    Contradicts { expected: &'static str, found: &'static str },
+    /// The input starts with FF FE 00 00, which could be a byte order mark for either UTF-32LE or UTF-16LE.
+    Ambiguous { expected: &'static str },
+    /// U+FEFF appears after the start of the text, where it is a zero width no-break space.
+    NotAtStart { index: usize }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::Contradicts { expected, found } => {
+                write!(f, "input starts with a {} byte order mark, but is being decoded as {}", found, expected)
+            }
+            Warning::Ambiguous { expected } => {
+                write!(f, "input starts with ff fe 00 00, which is a UTF-32LE byte order mark, or a UTF-16LE one followed by U+0000; it is being decoded as {}", expected)
+            }
+            Warning::NotAtStart { index } => {
+                write!(f, "byte order mark found in the middle of the text (character {})", index)
+            }
+        }
+    }
+}
+
+/// Check a decoded string for byte order marks that are in the wrong place or don't match its encoding.
+pub fn warnings(decoding: &DecodedString) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    // Byte order marks are at most 4 bytes long, so only FF FE 00 00 needs the rest of the input
+    let mut bytes = decoding.atoms.iter().flat_map(Atom::to_bytes);
+    let start: Vec<u8> = bytes.by_ref().take(UTF_32LE_MARK.len()).collect();
+    let found = if start == UTF_32LE_MARK {
+        Some(if is_utf32le(bytes) { UTF_32LE as EncodingRef } else { UTF_16LE })
+    } else {
+        sniff(&start).map(|(encoding, _)| encoding)
+    };
+
+    if let Some(encoding) = found {
+        let expected = decoding.encoding.name();
+        if encoding.name() != expected {
+            // Decoding as UTF-16LE is a reasonable reading of FF FE 00 00, so it isn't a contradiction
+            if start == UTF_32LE_MARK && EncodingForm::of(decoding.encoding) == Some(EncodingForm::Utf16(Endianness::Little)) {
+                warnings.push(Warning::Ambiguous { expected });
+            } else {
+                warnings.push(Warning::Contradicts { expected, found: encoding.name() });
+            }
+        }
+    }
+
+    for (index, atom) in decoding.atoms.iter().enumerate().skip(1) {
+        if atom.to_char() == BYTE_ORDER_MARK {
+            warnings.push(Warning::NotAtStart { index });
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf32_is_preferred_over_utf16() {
+        let (encoding, length) = sniff(&[0xff, 0xfe, 0x00, 0x00, 0x41, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(encoding.name(), "utf-32le");
+        assert_eq!(length, 4);
+    }
+
+    #[test]
+    fn utf16_is_preferred_when_the_rest_is_not_utf32() {
+        // A UTF-16LE byte order mark, U+0000, and "AB"
+        let bytes = [0xff, 0xfe, 0x00, 0x00, 0x41, 0x00, 0x42, 0x00];
+        let (encoding, length) = sniff(&bytes).unwrap();
+        assert_eq!(encoding.name(), "utf-16le");
+        assert_eq!(length, 2);
+
+        let decoding = DecodedString::decode(&bytes, UTF_16LE).unwrap();
+        assert!(warnings(&decoding).is_empty());
+    }
+
+    #[test]
+    fn utf16_decoding_of_a_possible_utf32_mark_is_ambiguous() {
+        let decoding = DecodedString::decode(&[0xff, 0xfe, 0x00, 0x00, 0x41, 0x00, 0x00, 0x00], UTF_16LE).unwrap();
+        assert_eq!(warnings(&decoding), vec![Warning::Ambiguous { expected: "utf-16le" }]);
+    }
+
+    #[test]
+    fn possible_utf32_mark_decoded_as_something_else() {
+        let decoding = DecodedString::decode(&[0xff, 0xfe, 0x00, 0x00, 0x41, 0x00, 0x42, 0x00], UTF_8).unwrap();
+        assert_eq!(warnings(&decoding), vec![Warning::Contradicts { expected: "utf-8", found: "utf-16le" }]);
+    }
+
+    #[test]
+    fn no_byte_order_mark() {
+        assert!(sniff(&[0x41, 0xfe, 0xff]).is_none());
+    }
+
+    #[test]
+    fn byte_order_mark_for_a_different_encoding() {
+        let decoding = DecodedString::decode(&[0xff, 0xfe, 0x41, 0x00], UTF_16BE).unwrap();
+        assert_eq!(warnings(&decoding), vec![Warning::Contradicts { expected: "utf-16be", found: "utf-16le" }]);
+    }
+
+    #[test]
+    fn byte_order_mark_in_the_middle() {
+        let decoding = DecodedString::decode(&[0xef, 0xbb, 0xbf, 0x41, 0xef, 0xbb, 0xbf], UTF_8).unwrap();
+        assert_eq!(warnings(&decoding), vec![Warning::NotAtStart { index: 2 }]);
+    }
+}
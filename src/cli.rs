@@ -1,96 +1,977 @@
 //! Functions for parsing command line input and displaying output.
 use colored::*;
+use std::cmp;
+use std::collections::HashSet;
+use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::process;
 use std::os::unix::ffi::OsStringExt;
 use encoding::types::EncodingRef;
-use clap::{Arg, App};
-use crate::decoding::DecodedString;
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use unicode_width::UnicodeWidthStr;
+use crate::bidi;
+use crate::bits;
+use crate::bom;
+use crate::confusables;
+use crate::decoding::{Atom, DecodeError, DecodeOptions, DecodedString, EncodingForm, ErrorHandling, ReplacementUnit};
+use crate::detection;
+use crate::diff::Diff;
+use crate::encodings;
+use crate::graphemes::Grapheme;
+use crate::html;
+use crate::invisibles;
+use crate::mojibake::{self, Repair};
+use crate::normalization::{self, Form};
+use crate::render::{RenderOptions, Renderer, TerminalRenderer};
+
+pub use crate::render::Radix;
 
 const LABEL_SIZE: u16 = 7; // "bytes: / chars:" labels
+const GRAPHEMES_LABEL_SIZE: u16 = 11; // "graphemes: " label
+const MIN_ROW_WIDTH: usize = 12; // Enough for a few bytes, even if the terminal is narrower than the labels
+const DETECTED_PREVIEW_LINES: usize = 20; // Lines of each detected decoding to show side by side
+const COLUMN_SEPARATOR: &str = " │ ";
 
-pub fn parse_command_line() -> (Vec<EncodingRef>, Vec<u8>) {
-    let matches = App::new("string-inspector")
-                          .version("0.0.1")
-                          .about("Inspects unicode strings")
-                          .arg(Arg::with_name("text")
-                              .index(1)
-                              .multiple(true))
-                          .arg(Arg::with_name("encoding")
-                               .short("e")
-                               .long("encoding")
-                               .value_name("ENCODING")
-                               .multiple(true)
-                               .number_of_values(1)
-                               .possible_values(&["utf8", "latin1"])
-                               .default_value("utf8")
-                               .help("Encoding to include in the output")
-                               .takes_value(true))
-
-                          .get_matches();
-
-        let encodings: Vec<EncodingRef> = matches.values_of("encoding").unwrap()
-        .map(|e| encoding::label::encoding_from_whatwg_label(e).unwrap())
-        .collect();
+/// Settings for how each decoding is displayed.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    /// Show the byte offset of each line of output.
+    pub offsets: Option<Radix>,
+    /// Show the unicode name and properties of each character.
+    pub names: bool,
+    /// Show which atoms are grouped into grapheme clusters.
+    pub graphemes: bool,
+    /// How to format the bytes and characters.
+    pub render: RenderOptions
+}
+
+/// How to present the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Coloured text for a terminal.
+    #[default]
+    Text,
+    /// JSON for other programs, if the `serde` feature is enabled.
+    Json,
+    /// A self-contained HTML page.
+    Html
+}
+
+/// Some bytes to inspect, and the encodings to decode them with.
+pub struct Input {
+    /// The file the bytes were read from, if any.
+    pub path: Option<String>,
+    pub buffer: Vec<u8>,
+    /// Where the bytes start within the file.
+    pub start: usize,
+    pub encodings: Vec<EncodingRef>,
+    /// Scores for the encodings, if they were detected automatically.
+    pub detected: Vec<(EncodingRef, f32)>
+}
+
+/// The inputs and settings chosen on the command line.
+#[derive(Default)]
+pub struct Options {
+    pub inputs: Vec<Input>,
+    /// How to handle invalid input when decoding.
+    pub decode: DecodeOptions,
+    pub display: DisplayOptions,
+    /// Look for text that was decoded with the wrong encoding, and suggest repairs.
+    pub mojibake: bool,
+    /// Report which normalization forms the text is in.
+    pub normalization: bool,
+    /// Show the text converted to a normalization form.
+    pub normalize: Option<Form>,
+    /// Look for characters that could be mistaken for characters from another script.
+    pub confusables: bool,
+    /// Exit with a non-zero status if any confusable characters are found.
+    pub fail_on_confusables: bool,
+    /// Look for bidirectional control characters that make text display differently to how it is stored.
+    pub bidi: bool,
+    /// List zero width and other invisible characters.
+    pub invisibles: bool,
+    /// Show the bits of each character's encoding, and how they make up its code point.
+    pub bits: bool,
+    /// Output the input with invisible characters removed, instead of inspecting it.
+    pub strip_invisibles: bool,
+    /// Whether to output coloured text, JSON or HTML.
+    pub format: Format,
+    /// Compare two inputs instead of inspecting them, if the diff subcommand was used.
+    pub diff: Option<(Input, Input)>
+}
+
+/// The command line arguments, and how to parse them.
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("string-inspector")
+        .version("0.0.1")
+        .about("Inspects unicode strings")
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::DisableHelpSubcommand)
+        // Without this, clap rejects any first word that looks like a misspelt subcommand, like "dif",
+        // and suggests "diff" instead. Since the first word is usually text to inspect, every word
+        // other than "diff" is accepted as text, so typos of the subcommand are inspected rather than reported.
+        .setting(AppSettings::AllowExternalSubcommands)
+        .arg(Arg::with_name("text")
+            .index(1)
+            .multiple(true)
+            .help("Text to inspect. To inspect text starting with the word diff, put -- before it"))
+        .arg(Arg::with_name("encoding")
+            .short("e")
+            .long("encoding")
+            .value_name("ENCODING")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(validate_encoding)
+            .default_value("utf8")
+            .help("Encoding to include in the output. If omitted, the encoding is taken from the byte order mark, or utf8 if there isn't one")
+            .takes_value(true))
+        .arg(Arg::with_name("detect")
+            .short("d")
+            .long("detect")
+            .conflicts_with("encoding")
+            .help("Guess the encoding, and show the most likely candidates"))
+        .arg(Arg::with_name("candidates")
+            .short("n")
+            .long("candidates")
+            .value_name("N")
+            .validator(validate_count)
+            .default_value("3")
+            .help("How many candidates to show with --detect")
+            .takes_value(true))
+        .arg(Arg::with_name("list-encodings")
+            .long("list-encodings")
+            .help("List the encodings that can be passed to --encoding, and exit"))
+        .arg(Arg::with_name("file")
+            .short("f")
+            .long("file")
+            .value_name("PATH")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Read the input from a file. Can be used more than once")
+            .takes_value(true))
+        .arg(Arg::with_name("offset")
+            .long("offset")
+            .value_name("BYTES")
+            .validator(validate_number)
+            .help("Skip this many bytes at the start of the input")
+            .takes_value(true))
+        .arg(Arg::with_name("length")
+            .long("length")
+            .value_name("BYTES")
+            .validator(validate_number)
+            .help("Only inspect this many bytes of the input")
+            .takes_value(true))
+        .arg(Arg::with_name("offsets")
+            .short("o")
+            .long("offsets")
+            .value_name("RADIX")
+            .possible_values(&["decimal", "hex", "upper-hex", "octal"])
+            .help("Show the byte offset at the start of each line")
+            .takes_value(true))
+        .arg(Arg::with_name("mojibake")
+            .short("m")
+            .long("mojibake")
+            .help("Check whether the text was decoded with the wrong encoding, and show the repaired text"))
+        .arg(Arg::with_name("names")
+            .long("names")
+            .help("Show the name, general category, script, block and unicode version of each character"))
+        .arg(Arg::with_name("graphemes")
+            .short("g")
+            .long("graphemes")
+            .help("Show which characters are grouped together into grapheme clusters"))
+        .arg(Arg::with_name("normalization")
+            .long("normalization")
+            .help("Report whether the text is in each unicode normalization form, and which characters would change"))
+        .arg(Arg::with_name("normalize")
+            .long("normalize")
+            .value_name("FORM")
+            .possible_values(&["nfc", "nfd", "nfkc", "nfkd"])
+            .case_insensitive(true)
+            .help("Also show the text converted to a unicode normalization form")
+            .takes_value(true))
+        .arg(Arg::with_name("confusables")
+            .long("confusables")
+            .help("Look for characters that could be mistaken for characters from another script"))
+        .arg(Arg::with_name("fail-on-confusables")
+            .long("fail-on-confusables")
+            .help("Like --confusables, but exit with status 1 if any are found"))
+        .arg(Arg::with_name("bidi")
+            .long("bidi")
+            .help("Look for bidirectional control characters, and show how the affected lines are displayed"))
+        .arg(Arg::with_name("invisibles")
+            .long("invisibles")
+            .help("List zero width, format and other invisible characters, with suggested replacements"))
+        .arg(Arg::with_name("bits")
+            .long("bits")
+            .help("Show the bits of each character in UTF-8, UTF-16 or UTF-32, split into marker bits and the bits of the code point"))
+        .arg(Arg::with_name("strip-invisibles")
+            .long("strip-invisibles")
+            .help("Output the input with invisible characters removed, instead of inspecting it"))
+        .arg(Arg::with_name("radix")
+            .long("radix")
+            .value_name("RADIX")
+            .possible_values(&["hex", "upper-hex", "decimal", "octal", "binary"])
+            .default_value("hex")
+            .help("How to write each byte")
+            .takes_value(true))
+        .arg(Arg::with_name("errors")
+            .long("errors")
+            .value_name("HANDLER")
+            .possible_values(&["strict", "replace", "ignore", "surrogateescape"])
+            .default_value("replace")
+            .help("How to handle invalid input: stop at the first error, replace it with U+FFFD, skip it, or escape each byte as a surrogate like Python")
+            .takes_value(true))
+        .arg(Arg::with_name("maximal-subparts")
+            .long("maximal-subparts")
+            .help("Replace each maximal subpart of an invalid sequence with a single U+FFFD, like browsers, Python and Java, instead of each byte"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .value_name("FORMAT")
+            .possible_values(if cfg!(feature = "serde") { &["text", "json", "html"] } else { &["text", "html"] })
+            .default_value("text")
+            .help("Output format. JSON and HTML output include every atom of each decoding, and ignore the other display options")
+            .takes_value(true))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Compares two inputs and explains the first difference. Exits with status 1 if they are different. Must come before any other arguments")
+            .arg(Arg::with_name("left")
+                .index(1)
+                .required(true))
+            .arg(Arg::with_name("right")
+                .index(2)
+                .required(true))
+            .arg(Arg::with_name("encoding")
+                .short("e")
+                .long("encoding")
+                .value_name("ENCODING")
+                .multiple(true)
+                .number_of_values(1)
+                .max_values(2)
+                .validator(validate_encoding)
+                .default_value("utf8")
+                .help("Encoding to decode the inputs with. Pass it twice to use a different encoding for each input")
+                .takes_value(true))
+            .arg(Arg::with_name("files")
+                .long("files")
+                .conflicts_with("hex")
+                .help("Treat the inputs as paths to files"))
+            .arg(Arg::with_name("hex")
+                .long("hex")
+                .help("Treat the inputs as bytes written in hex, like \"c3 a9\"")))
+}
+
+pub fn parse_command_line() -> Options {
+    let matches = app().get_matches();
+
+    if matches.is_present("list-encodings") {
+        display_encodings();
+        process::exit(0);
+    }
+
+    if let Some(diff_matches) = matches.subcommand_matches("diff") {
+        return Options { diff: Some(parse_diff_inputs(diff_matches)), ..Options::default() };
+    }
 
-    let text = matches.values_of_os("text");
+    let start: usize = matches.value_of("offset").map_or(0, |n| n.parse().unwrap());
+    let length: Option<usize> = matches.value_of("length").map(|n| n.parse().unwrap());
 
-    let buffer = match text {
-        Some(args) => {
-            let mut arg_bytes: Vec<Vec<u8>> = Vec::new();
-            for arg in args {
-                arg_bytes.push(arg.to_owned().into_vec());
+    // Each buffer has its own start, because the offset may be past the end of some inputs
+    let mut buffers: Vec<(Option<String>, usize, Vec<u8>)> = Vec::new();
+
+    if let Some(args) = matches.values_of_os("text") {
+        let mut arg_bytes: Vec<Vec<u8>> = Vec::new();
+        for arg in args {
+            arg_bytes.push(arg.to_owned().into_vec());
+        }
+
+        let text = arg_bytes.join(&0x20);
+        buffers.push((None, cmp::min(start, text.len()), slice(&text, start, length)));
+    }
+
+    if let Some(paths) = matches.values_of("file") {
+        for path in paths {
+            match read_file(path, start, length) {
+                Ok((file_start, buffer)) => buffers.push((Some(path.to_owned()), file_start, buffer)),
+                Err(err) => {
+                    eprintln!("Unable to read {}: {}", path, err);
+                    process::exit(1);
+                }
             }
+        }
+    }
 
-            arg_bytes.join(&0x20)
+    if buffers.is_empty() {
+        eprintln!("No arguments passed to program: reading text from standard input...");
+        let mut result: Vec<u8> = Vec::new();
+        io::stdin().read_to_end(&mut result).expect("Unable to read from stdin");
+        buffers.push((None, cmp::min(start, result.len()), slice(&result, start, length)));
+    }
+
+    let candidates: usize = matches.value_of("candidates").unwrap().parse().unwrap();
+
+    let inputs = buffers.into_iter().map(|(path, start, buffer)| {
+        if matches.is_present("detect") {
+            let detected: Vec<(EncodingRef, f32)> = detection::detect(&buffer).into_iter().take(candidates).collect();
+            let encodings = detected.iter().map(|&(encoding, _)| encoding).collect();
+            return Input { path, buffer, start, encodings, detected };
         }
-        None => {
-            eprintln!("No arguments passed to program: reading text from standard input...");
-            let mut result: Vec<u8> = Vec::new();
-            io::stdin().read_to_end(&mut result).expect("Unable to read from stdin");
-            result
+
+        // Without an explicit encoding, trust the byte order mark if there is one
+        let sniffed_encoding = match matches.occurrences_of("encoding") {
+            0 => bom::sniff(&buffer).map(|(encoding, _)| encoding),
+            _ => None
+        };
+
+        let encodings: Vec<EncodingRef> = match sniffed_encoding {
+            Some(encoding) => vec![encoding],
+            None => matches.values_of("encoding").unwrap()
+                .map(|e| encodings::encoding_from_label(e).unwrap())
+                .collect()
+        };
+
+        Input { path, buffer, start, encodings, detected: Vec::new() }
+    }).collect();
+
+    let offsets = matches.value_of("offsets").and_then(Radix::from_name);
+
+    let display = DisplayOptions {
+        offsets,
+        names: matches.is_present("names"),
+        graphemes: matches.is_present("graphemes"),
+        render: RenderOptions {
+            byte_radix: matches.value_of("radix").and_then(Radix::from_name).unwrap_or_default(),
+            ..RenderOptions::default()
         }
     };
 
-    (encodings, buffer)
+    let decode = DecodeOptions {
+        errors: match matches.value_of("errors") {
+            Some("strict") => ErrorHandling::Strict,
+            Some("ignore") => ErrorHandling::Ignore,
+            Some("surrogateescape") => ErrorHandling::SurrogateEscape,
+            _ => ErrorHandling::Replace
+        },
+        replacement_unit: if matches.is_present("maximal-subparts") { ReplacementUnit::MaximalSubpart } else { ReplacementUnit::CodeUnit }
+    };
+
+    Options {
+        inputs,
+        decode,
+        display,
+        mojibake: matches.is_present("mojibake"),
+        normalization: matches.is_present("normalization"),
+        normalize: matches.value_of("normalize").and_then(Form::from_name),
+        confusables: matches.is_present("confusables") || matches.is_present("fail-on-confusables"),
+        fail_on_confusables: matches.is_present("fail-on-confusables"),
+        bidi: matches.is_present("bidi"),
+        invisibles: matches.is_present("invisibles"),
+        bits: matches.is_present("bits"),
+        strip_invisibles: matches.is_present("strip-invisibles"),
+        format: match matches.value_of("format") {
+            Some("json") => Format::Json,
+            Some("html") => Format::Html,
+            _ => Format::Text
+        },
+        diff: None
+    }
+}
+
+/// Read the two inputs of the diff subcommand.
+fn parse_diff_inputs(matches: &ArgMatches) -> (Input, Input) {
+    let encodings: Vec<EncodingRef> = matches.values_of("encoding").unwrap()
+        .map(|e| encodings::encoding_from_label(e).unwrap())
+        .collect();
+
+    let read_input = |name: &str, encoding: EncodingRef| {
+        let arg = matches.value_of_os(name).unwrap();
+        let (path, buffer) = if matches.is_present("files") {
+            let path = arg.to_string_lossy().into_owned();
+            let (_, buffer) = read_file(&path, 0, None).unwrap_or_else(|err| {
+                eprintln!("Unable to read {}: {}", path, err);
+                process::exit(1);
+            });
+            (Some(path), buffer)
+        } else if matches.is_present("hex") {
+            let buffer = parse_hex(&arg.to_string_lossy()).unwrap_or_else(|err| {
+                eprintln!("Unable to read {} input: {}", name, err);
+                process::exit(1);
+            });
+            (None, buffer)
+        } else {
+            (None, arg.to_owned().into_vec())
+        };
+
+        Input { path, buffer, start: 0, encodings: vec![encoding], detected: Vec::new() }
+    };
+
+    let left = read_input("left", encodings[0]);
+    let right = read_input("right", *encodings.last().unwrap());
+    (left, right)
+}
+
+/// Parse bytes written in hex, ignoring whitespace.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err("hex must have an even number of digits".to_owned());
+    }
+
+    digits.chunks(2).map(|pair| {
+        let byte: String = pair.iter().collect();
+        u8::from_str_radix(&byte, 16).map_err(|_| format!("{} is not a hex byte", byte))
+    }).collect()
 }
 
-pub fn display_decoding(decoding: &DecodedString, max_line_width: usize) {
+fn validate_encoding(label: String) -> Result<(), String> {
+    if encodings::encoding_from_label(&label).is_some() {
+        return Ok(());
+    }
+
+    let suggestions = encodings::suggestions(&label);
+    if suggestions.is_empty() {
+        Err(format!("unknown encoding '{}'. Use --list-encodings to see the supported encodings", label))
+    } else {
+        Err(format!("unknown encoding '{}'. Did you mean {}?", label, suggestions.join(", ")))
+    }
+}
+
+fn validate_number(value: String) -> Result<(), String> {
+    value.parse::<usize>().map(|_| ()).map_err(|_| format!("{} is not a number of bytes", value))
+}
+
+fn validate_count(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("{} is not a number", value))
+    }
+}
+
+/// Take up to `length` bytes, starting from `start`.
+fn slice(bytes: &[u8], start: usize, length: Option<usize>) -> Vec<u8> {
+    let start = cmp::min(start, bytes.len());
+    let end = length.map_or(bytes.len(), |length| cmp::min(start.saturating_add(length), bytes.len()));
+    bytes[start..end].to_vec()
+}
+
+/// Read up to `length` bytes of a file, starting from `start`, without reading the rest of it.
+///
+/// Returns where the bytes start, which is the end of the file if `start` is past it, and the bytes.
+fn read_file(path: &str, start: usize, length: Option<usize>) -> io::Result<(usize, Vec<u8>)> {
+    let mut file = File::open(path)?;
+    let start = cmp::min(start as u64, file.metadata()?.len());
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut buffer = Vec::new();
+    match length {
+        Some(length) => file.take(length as u64).read_to_end(&mut buffer)?,
+        None => file.read_to_end(&mut buffer)?
+    };
+    Ok((start as usize, buffer))
+}
+
+/// The number of columns left for the rows of bytes and characters, after `used` columns of labels and offsets.
+fn row_width(max_line_width: usize, used: usize) -> usize {
+    cmp::max(max_line_width.saturating_sub(used), MIN_ROW_WIDTH)
+}
+
+pub fn display_decoding(decoding: &DecodedString, display: &DisplayOptions, max_line_width: usize, start: usize) {
+    let renderer = TerminalRenderer::new(display.render.clone());
     println!("[{}]", decoding.encoding.name());
 
-    let chunks = decoding.wrap_lines(max_line_width - LABEL_SIZE as usize);
+    for warning in decoding.byte_order_mark_warnings() {
+        println!("{}", format!("warning: {}", warning).yellow());
+    }
+
+    // The gutter needs to be wide enough for the largest offset, plus a space
+    let end = start + decoding.atoms.last().map_or(0, |atom| atom.byte_range().end);
+    let offset_width = display.offsets.map_or(0, |radix| radix.format(end, 0).len());
+    let gutter_size = display.offsets.map_or(0, |_| offset_width + 1);
+    let label_size = if display.graphemes { GRAPHEMES_LABEL_SIZE } else { LABEL_SIZE } as usize;
+
+    let chunks = renderer.wrap_lines(decoding, row_width(max_line_width, label_size + gutter_size));
     let mut first = true;
 
     for chunk in chunks.iter() {
         if first {
             first = false;
         } else {
-            println!("");
+            println!();
+        }
+
+        if let Some(radix) = display.offsets {
+            let offset = start + chunk.atoms.first().map_or(0, |atom| atom.offset());
+            print!("{} ", radix.format(offset, offset_width).dimmed());
         }
+        print!("{:width$}", "bytes:", width = label_size);
+        println!("{}", renderer.bytes_row(&chunk.atoms));
 
-        print!("bytes: ");
-        println!("{}", chunk.format_bytes());
+        print!("{:width$}", "", width = gutter_size);
+        print!("{:width$}", "chars:", width = label_size);
+        println!("{}", renderer.characters_row(&chunk.atoms));
 
-        print!("chars: ");
-        println!("{}", chunk.format_characters());
+        if display.graphemes {
+            print!("{:width$}", "", width = gutter_size);
+            print!("{:width$}", "graphemes:", width = label_size);
+            println!("{}", renderer.graphemes_row(chunk));
+        }
+
+        if display.names {
+            for row in format_names(chunk, &display.render) {
+                println!("{:width$}{}", "", row.dimmed(), width = gutter_size + label_size);
+            }
+        }
+
+        for row in format_legend(chunk) {
+            println!("{:width$}{}", "", row.yellow(), width = gutter_size + label_size);
+        }
     }
 
-    println!("");
-    println!("{}", highlight_non_ascii(&decoding.to_string()));
+    println!();
+    println!("{}", highlight_non_ascii(&decoding.output()));
 }
 
-pub fn display_decodings(decodings: &Vec<DecodedString>, max_line_width: usize) {
+pub fn display_decodings(decodings: &[DecodedString], display: &DisplayOptions, max_line_width: usize, start: usize) {
     let mut first = true;
     for decoded_string in decodings.iter() {
         if first {
             first = false;
         } else {
-            println!("");
+            println!();
         }
-        display_decoding(&decoded_string, max_line_width);
+        display_decoding(decoded_string, display, max_line_width, start);
     }
 }
 
+/// Report that an input could not be decoded, on stderr so that it doesn't get mixed up with the output.
+pub fn display_decode_error(path: Option<&str>, error: &DecodeError) {
+    eprintln!("{}", format!("error: unable to decode {}: {}", path.unwrap_or("input"), error).red());
+}
+
+/// Display the text of the most likely decodings side by side, each headed by its encoding and score.
+pub fn display_detected_encodings(detected: &[(EncodingRef, f32)], decodings: &[DecodedString], max_line_width: usize) {
+    let columns: Vec<(String, String)> = decodings.iter().map(|decoding| {
+        let name = decoding.encoding.name();
+        let score = detected.iter().find(|(encoding, _)| encoding.name() == name).map_or(0.0, |&(_, score)| score);
+        (format!("{} {:.2}", name, score), decoding.output())
+    }).collect();
+
+    println!("{}", "Most likely encodings:".bold());
+    let rows = side_by_side(&columns, max_line_width);
+    for (number, row) in rows.iter().enumerate().take(DETECTED_PREVIEW_LINES + 1) {
+        if number == 0 {
+            println!("{}", row.bold());
+        } else {
+            println!("{}", row);
+        }
+    }
+    if rows.len() > DETECTED_PREVIEW_LINES + 1 {
+        println!("{}", "...".dimmed());
+    }
+    println!();
+}
+
+/// Lay out some text in columns next to each other, each with a header, splitting `max_line_width` between them.
+///
+/// Each column is wrapped to fit, and control characters are escaped. The first row is the headers.
+fn side_by_side(columns: &[(String, String)], max_line_width: usize) -> Vec<String> {
+    let gaps = COLUMN_SEPARATOR.chars().count() * columns.len().saturating_sub(1);
+    let width = cmp::max(max_line_width.saturating_sub(gaps) / cmp::max(columns.len(), 1), MIN_ROW_WIDTH);
+
+    let cells: Vec<Vec<String>> = columns.iter().map(|(header, text)| {
+        let mut lines = vec![header.clone()];
+        lines.extend(wrap_text(text, width));
+        lines
+    }).collect();
+
+    let rows = cells.iter().map(Vec::len).max().unwrap_or(0);
+    (0..rows).map(|row| {
+        // Columns that have run out of lines are left off the end, rather than padded
+        let used = cells.iter().rposition(|lines| row < lines.len()).map_or(0, |last| last + 1);
+        let line: Vec<String> = cells[..used].iter().map(|lines| {
+            let cell = lines.get(row).map_or("", String::as_str);
+            format!("{}{}", cell, " ".repeat(width.saturating_sub(UnicodeWidthStr::width(cell))))
+        }).collect();
+        line.join(COLUMN_SEPARATOR).trim_end().to_owned()
+    }).collect()
+}
+
+/// Split text into lines of at most `width` columns, breaking at newlines and escaping other control characters.
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+
+    for character in text.chars() {
+        if character == '\n' {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+            continue;
+        }
+
+        let shown = if character.is_control() { character.escape_default().to_string() } else { character.to_string() };
+        let shown_width = UnicodeWidthStr::width(shown.as_str());
+        if line_width + shown_width > width && !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        }
+        line.push_str(&shown);
+        line_width += shown_width;
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Explain any mojibake in a decoding, and show the most likely repair.
+pub fn display_mojibake(decoding: &DecodedString, display: &DisplayOptions, max_line_width: usize, start: usize) {
+    let repairs = mojibake::analyse(decoding);
+    let best = match repairs.first() {
+        Some(best) => best,
+        None => {
+            println!("{}", format!("No mojibake found in [{}]", decoding.encoding.name()).bold());
+            return;
+        }
+    };
+
+    let explanations: Vec<String> = repairs.iter().take(3).map(Repair::to_string).collect();
+    let explanation_width = explanations.iter().map(String::len).max().unwrap_or(0);
+
+    println!("{}", format!("Possible mojibake in [{}]:", decoding.encoding.name()).bold());
+    for (explanation, repair) in explanations.iter().zip(repairs.iter()) {
+        println!("  {:width$}  {:.2}", explanation, repair.score, width = explanation_width);
+    }
+    println!();
+
+    println!("{}", format!("Repaired as {}:", best).bold());
+    display_decoding(&best.repaired, display, max_line_width, start);
+}
+
+/// Report which normalization forms a decoding is in, and which characters would change in the others.
+pub fn display_normalization(decoding: &DecodedString) {
+    println!("{}", format!("Normalization of [{}]:", decoding.encoding.name()).bold());
+
+    for report in normalization::analyse(decoding) {
+        if report.is_normalized() {
+            println!("  {:4}  {}", report.form, "yes".green());
+        } else {
+            let indexes: Vec<String> = report.changed.iter().map(usize::to_string).collect();
+            println!("  {:4}  {}   changes characters {}", report.form, "no".red(), indexes.join(", "));
+        }
+    }
+}
+
+/// Show the bytes and characters of a decoding after converting it to a normalization form.
+pub fn display_normalized(decoding: &DecodedString, form: Form, display: &DisplayOptions, max_line_width: usize) {
+    // Normalizing works on the decoded text, which has lost the bytes of any invalid code units
+    let invalid = decoding.atoms.iter().filter(|atom| !atom.is_valid()).count();
+    if invalid > 0 {
+        let plural = if invalid == 1 { "" } else { "s" };
+        println!("{}", format!("warning: can't show the {} form of [{}], because the input has {} invalid code unit{}", form, decoding.encoding.name(), invalid, plural).yellow());
+        return;
+    }
+
+    match normalization::normalize(decoding, form) {
+        Some(normalized) => {
+            println!("{}", format!("Normalized to {}:", form).bold());
+            display_decoding(&normalized, display, max_line_width, 0);
+        }
+        None => {
+            println!("{}", format!("warning: the {} form of the text can't be encoded in {}", form, decoding.encoding.name()).yellow());
+        }
+    }
+}
+
+/// Explain which characters of a decoding could be mistaken for others, and highlight them.
+pub fn display_confusables(decoding: &DecodedString, report: &confusables::Report, render: &RenderOptions, max_line_width: usize) {
+    println!("{}", format!("Confusables in [{}]:", decoding.encoding.name()).bold());
+
+    let scripts = if report.scripts.is_empty() { "none".to_owned() } else { report.scripts.join(", ") };
+    if report.mixed_script {
+        println!("  scripts:  {} {}", scripts, "(mixed)".yellow());
+    } else {
+        println!("  scripts:  {}", scripts);
+    }
+    println!("  skeleton: {}", report.skeleton);
+
+    if report.is_suspicious() {
+        println!("  {}", format!("suspicious characters: {}", report.suspicious.len()).red());
+        display_highlighted(decoding, &report.suspicious, render, max_line_width);
+    } else {
+        println!("  no suspicious characters");
+    }
+}
+
+/// Show each line of a decoding that contains bidirectional control characters, in logical and visual order.
+pub fn display_bidi(decoding: &DecodedString) {
+    let lines = bidi::analyse(decoding);
+    if lines.is_empty() {
+        println!("{}", format!("No bidirectional control characters in [{}]", decoding.encoding.name()).bold());
+        return;
+    }
+
+    println!("{}", format!("Bidirectional control characters in [{}]:", decoding.encoding.name()).bold());
+    for line in lines.iter() {
+        print!("  line {}: {} controls", line.number, line.controls.len());
+        if line.unterminated.is_empty() {
+            println!();
+        } else {
+            let unterminated: Vec<String> = line.unterminated.iter()
+                .map(|&index| format!("{} at character {}", bidi::abbreviation(decoding.atoms[index].to_char()).unwrap(), index))
+                .collect();
+            println!(", {}", format!("unterminated: {}", unterminated.join(", ")).red());
+        }
+
+        println!("    logical: {}", bidi::mark_controls(&line.logical));
+        println!("    visual:  {}", bidi::mark_controls(&line.visual));
+    }
+}
+
+/// List the invisible characters in a decoding, and what to replace them with.
+pub fn display_invisibles(decoding: &DecodedString, offsets: Option<Radix>, start: usize) {
+    let found = invisibles::find(decoding);
+    if found.is_empty() {
+        println!("{}", format!("No invisible characters in [{}]", decoding.encoding.name()).bold());
+        return;
+    }
+
+    let radix = offsets.unwrap_or(Radix::Decimal);
+    let rows: Vec<(String, String, String, String)> = found.iter().map(|invisible| (
+        radix.format(start + invisible.offset, 0),
+        format!("U+{:04X}", invisible.character as u32),
+        invisible.name.clone().unwrap_or_else(|| "<unnamed>".to_owned()),
+        match invisible.replacement {
+            None => "keep, part of a sequence".to_owned(),
+            Some("") => "remove".to_owned(),
+            Some(replacement) => format!("replace with {:?}", replacement)
+        }
+    )).collect();
+
+    let offset_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max("offset".len());
+    let code_point_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    let name_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+
+    println!("{}", format!("Invisible characters in [{}]:", decoding.encoding.name()).bold());
+    println!("  {:ow$}  {:cw$}  {:nw$}  suggestion", "offset", "", "name", ow = offset_width, cw = code_point_width, nw = name_width);
+    for (offset, code_point, name, suggestion) in rows.iter() {
+        println!("  {:ow$}  {:cw$}  {:nw$}  {}", offset, code_point, name, suggestion, ow = offset_width, cw = code_point_width, nw = name_width);
+    }
+}
+
+/// Show how the bits of each character's code units make up its code point.
+pub fn display_bits(decoding: &DecodedString, render: &RenderOptions) {
+    let form = match EncodingForm::of(decoding.encoding) {
+        Some(form) => form,
+        None => {
+            println!("{}", format!("Bits can only be shown for UTF-8, UTF-16 and UTF-32, not [{}]", decoding.encoding.name()).bold());
+            return;
+        }
+    };
+
+    println!("{}", format!("Bits of [{}]:", decoding.encoding.name()).bold());
+    let renderer = TerminalRenderer::new(render.clone());
+    let digits = render.byte_radix.byte_digits();
+    // Wide enough for the bytes of one code unit, separated by spaces
+    let bytes_width = form.code_unit_size() * (digits + 1) - 1;
+
+    for atom in decoding.atoms.iter() {
+        let c = match atom {
+            Atom::Character(c) | Atom::ByteOrderMark(c) => c,
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => {
+                println!("  {}  {}", renderer.render_bytes(atom).trim(), format!("invalid: {}", u.reason).red());
+                continue;
+            }
+        };
+
+        println!("  U+{:04X} {:?}", c.character as u32, c.character);
+        let breakdown = match bits::breakdown(c, decoding.encoding) {
+            Some(breakdown) => breakdown,
+            None => {
+                println!("    {}", "not encoded the usual way".yellow());
+                continue;
+            }
+        };
+
+        for unit in breakdown.units.iter() {
+            let bytes: Vec<String> = unit.bytes.iter().map(|&byte| render.byte_radix.format(byte as usize, digits)).collect();
+            // Unused bits are bracketed, to tell them apart from marker bits
+            let mut bits = Vec::new();
+            if !unit.unused.is_empty() {
+                bits.push(format!("[{}]", unit.unused).dimmed().to_string());
+            }
+            if !unit.marker.is_empty() {
+                bits.push(unit.marker.dimmed().to_string());
+            }
+            bits.push(unit.payload.clone());
+            println!("    {:width$}  {}", bytes.join(" "), bits.join(" "), width = bytes_width);
+        }
+
+        let payloads: Vec<&str> = breakdown.units.iter().map(|unit| unit.payload.as_str()).collect();
+        let offset = if breakdown.offset == 0 { String::new() } else { format!(" + 0x{:x}", breakdown.offset) };
+        println!("    {:width$}  {}{} = U+{:04X}", "", payloads.join(" "), offset, breakdown.code_point, width = bytes_width);
+    }
+}
+
+/// Show two decodings one after the other with their differences highlighted, and explain the first difference.
+pub fn display_diff(diff: &Diff, left_label: &str, right_label: &str, render: &RenderOptions, max_line_width: usize) {
+    println!("{}", format!("--- {} [{}]", left_label, diff.left.encoding.name()).bold());
+    display_highlighted(diff.left, &diff.changed_left(), render, max_line_width);
+    println!();
+    println!("{}", format!("+++ {} [{}]", right_label, diff.right.encoding.name()).bold());
+    display_highlighted(diff.right, &diff.changed_right(), render, max_line_width);
+    println!();
+
+    match diff.first_divergence() {
+        Some(divergence) => println!(
+            "First difference at byte {} of {} and byte {} of {}: {}",
+            divergence.left_offset, left_label, divergence.right_offset, right_label, divergence.cause
+        ),
+        None => println!("No differences")
+    }
+}
+
+/// Renders atoms like the terminal output, but only colours the highlighted ones.
+struct Highlighter {
+    terminal: TerminalRenderer,
+    /// The indexes of the atoms to highlight.
+    highlighted: HashSet<usize>
+}
+
+impl Renderer for Highlighter {
+    fn render_bytes(&self, atom: &Atom) -> String {
+        self.terminal.render_bytes(atom)
+    }
+
+    fn render_character(&self, atom: &Atom) -> String {
+        self.terminal.render_character(atom)
+    }
+
+    fn render_grapheme(&self, grapheme: &Grapheme) -> String {
+        self.terminal.render_grapheme(grapheme)
+    }
+
+    fn width(&self, atom: &Atom) -> usize {
+        self.terminal.width(atom)
+    }
+
+    fn decorate(&self, _position: usize, atoms: &[Atom], output: String) -> String {
+        if atoms.iter().any(|atom| self.highlighted.contains(&atom.index())) {
+            output.red().bold().to_string()
+        } else {
+            output
+        }
+    }
+}
+
+/// Show the bytes and characters of a decoding, highlighting some of the atoms.
+fn display_highlighted(decoding: &DecodedString, highlighted: &[usize], render: &RenderOptions, max_line_width: usize) {
+    let renderer = Highlighter { terminal: TerminalRenderer::new(render.clone()), highlighted: highlighted.iter().copied().collect() };
+
+    let mut first = true;
+    for chunk in renderer.wrap_lines(decoding, row_width(max_line_width, LABEL_SIZE as usize)) {
+        if first {
+            first = false;
+        } else {
+            println!();
+        }
+
+        println!("bytes: {}", renderer.bytes_row(&chunk.atoms));
+        println!("chars: {}", renderer.characters_row(&chunk.atoms));
+    }
+}
+
+/// Print the decodings of each input as a single JSON document.
+#[cfg(feature = "serde")]
+pub fn display_json(inputs: &[(&Input, Vec<DecodedString>)]) {
+    let inputs: Vec<serde_json::Value> = inputs.iter().map(|(input, decodings)| {
+        let detected: Vec<serde_json::Value> = input.detected.iter()
+            .map(|(encoding, score)| serde_json::json!({"encoding": encoding.name(), "score": score}))
+            .collect();
+
+        serde_json::json!({
+            "path": input.path,
+            "start": input.start,
+            "detected": detected,
+            "decodings": decodings
+        })
+    }).collect();
+
+    println!("{}", serde_json::to_string_pretty(&serde_json::json!({"inputs": inputs})).unwrap());
+}
+
+/// Print the decodings of each input as an HTML page.
+pub fn display_html(inputs: &[(&Input, Vec<DecodedString>)], render: &RenderOptions, max_line_width: usize) {
+    let inputs: Vec<(Option<&str>, usize, &[DecodedString])> = inputs.iter()
+        .map(|(input, decodings)| (input.path.as_deref(), input.start, decodings.as_slice()))
+        .collect();
+    print!("{}", html::page(&inputs, render, max_line_width));
+}
+
+/// List every supported encoding with its aliases.
+pub fn display_encodings() {
+    for (encoding, labels) in encodings::known_encodings() {
+        let name = encodings::canonical_name(encoding);
+        let aliases: Vec<&str> = labels.into_iter().filter(|&label| label != name).collect();
+
+        if aliases.is_empty() {
+            println!("{}", name.bold());
+        } else {
+            println!("{} ({})", name.bold(), aliases.join(", "));
+        }
+    }
+}
+
+/// Display the name of a file above the decodings of its contents.
+pub fn display_file_header(path: &str) {
+    println!("{}", format!("==> {} <==", path).bold());
+}
+
+/// Describe each atom in a table, with one row per atom and the columns lined up.
+fn format_names(decoding: &DecodedString, render: &RenderOptions) -> Vec<String> {
+    let renderer = TerminalRenderer::new(render.clone());
+    let rows: Vec<Vec<String>> = decoding.atoms.iter().map(|atom| {
+        match atom {
+            Atom::Character(c) | Atom::ByteOrderMark(c) => vec![
+                format!("U+{:04X}", c.character as u32),
+                c.name().unwrap_or_else(|| "<unnamed>".to_owned()),
+                c.general_category().to_owned(),
+                c.script().to_owned(),
+                c.block().unwrap_or("<no block>").to_owned(),
+                c.age().unwrap_or_else(|| "?".to_owned())
+            ],
+            _ => vec![renderer.render_bytes(atom).trim().to_owned(), "<invalid>".to_owned()]
+        }
+    }).collect();
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.chars().count()).max().unwrap_or(0))
+        .collect();
+
+    rows.iter().map(|row| {
+        let cells: Vec<String> = row.iter().zip(widths.iter())
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect();
+        cells.join("  ").trim_end().to_owned()
+    }).collect()
+}
+
+/// Explain the errors in a decoding, with each distinct explanation only appearing once.
+fn format_legend(decoding: &DecodedString) -> Vec<String> {
+    let mut rows: Vec<String> = Vec::new();
+    for atom in decoding.atoms.iter() {
+        if let Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) = atom {
+            let row = format!("{}: {}", u.reason, u.explanation());
+            if !rows.contains(&row) {
+                rows.push(row);
+            }
+        }
+    }
+    rows
+}
+
 fn highlight_non_ascii(input: &str) -> String {
     let mut output = String::new();
 
@@ -103,4 +984,109 @@ fn highlight_non_ascii(input: &str) -> String {
     }
 
     output
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_lined_up() {
+        let decoding = DecodedString::decode(&[0x41, 0xc3, 0xa9, 0xff], encoding::all::UTF_8).unwrap();
+        assert_eq!(format_names(&decoding, &RenderOptions::default()), vec![
+            "U+0041  LATIN CAPITAL LETTER A           Lu  Latin  Basic Latin         1.1",
+            "U+00E9  LATIN SMALL LETTER E WITH ACUTE  Ll  Latin  Latin-1 Supplement  1.1",
+            "ff      <invalid>"
+        ]);
+
+        let octal = RenderOptions { byte_radix: Radix::Octal, ..RenderOptions::default() };
+        assert_eq!(format_names(&decoding, &octal)[2], "377     <invalid>");
+    }
+
+    #[test]
+    fn errors_are_explained_once() {
+        let decoding = DecodedString::decode(&[0xe2, 0x82, 0x41, 0x80, 0x80, 0xc3], encoding::all::UTF_8).unwrap();
+        assert_eq!(format_legend(&decoding), vec![
+            "truncated sequence: expected a 3 byte sequence, found 2 bytes followed by 41",
+            "unexpected continuation byte: expected a lead byte, found continuation byte 80",
+            "truncated sequence: expected a 2 byte sequence, found 1 byte followed by the end of the input"
+        ]);
+    }
+
+    #[test]
+    fn detected_decodings_side_by_side() {
+        let columns = vec![
+            ("utf-8 0.90".to_owned(), "Größe\n日本".to_owned()),
+            ("windows-1252 0.50".to_owned(), "GrÃ¶ÃŸe".to_owned())
+        ];
+        assert_eq!(side_by_side(&columns, 40), vec![
+            "utf-8 0.90         │ windows-1252 0.50",
+            "Größe              │ GrÃ¶ÃŸe",
+            "日本"
+        ]);
+    }
+
+    #[test]
+    fn wrapped_text_escapes_control_characters() {
+        assert_eq!(wrap_text("ab\tcdefghijklm", 12), vec!["ab\\tcdefghij", "klm"]);
+    }
+
+    #[test]
+    fn diff_must_come_first() {
+        let matches = app().get_matches_from(vec!["string-inspector", "diff", "a", "b"]);
+        assert!(matches.subcommand_matches("diff").is_some());
+
+        let matches = app().get_matches_from(vec!["string-inspector", "--", "diff", "a"]);
+        assert!(matches.subcommand_matches("diff").is_none());
+        assert_eq!(matches.values_of("text").unwrap().collect::<Vec<&str>>(), vec!["diff", "a"]);
+    }
+
+    #[test]
+    fn at_least_one_candidate() {
+        assert!(app().get_matches_from_safe(vec!["string-inspector", "-d", "-n", "0", "abc"]).is_err());
+        assert!(app().get_matches_from_safe(vec!["string-inspector", "-d", "-n", "1", "abc"]).is_ok());
+    }
+
+    #[test]
+    fn other_words_are_text_even_if_they_look_like_diff() {
+        for &first in ["dif", "diffs", "hello"].iter() {
+            let matches = app().get_matches_from(vec!["string-inspector", first, "b"]);
+            assert!(matches.subcommand_name().is_none());
+            assert_eq!(matches.values_of("text").unwrap().collect::<Vec<&str>>(), vec![first, "b"]);
+        }
+    }
+
+    #[test]
+    fn hex_input() {
+        assert_eq!(parse_hex("c3 a9\n41"), Ok(vec![0xc3, 0xa9, 0x41]));
+        assert!(parse_hex("c3a").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn slice_within_input() {
+        assert_eq!(slice(b"hello world", 6, Some(3)), b"wor".to_vec());
+    }
+
+    #[test]
+    fn slice_past_end_of_input() {
+        assert_eq!(slice(b"hello", 3, Some(10)), b"lo".to_vec());
+        assert_eq!(slice(b"hello", 10, None), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn read_file_past_end() {
+        let path = std::env::temp_dir().join("string-inspector-read-file-past-end");
+        std::fs::write(&path, b"hello").unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(read_file(path, 3, None).unwrap(), (3, b"lo".to_vec()));
+        assert_eq!(read_file(path, 10, Some(2)).unwrap(), (5, Vec::new()));
+    }
+
+    #[test]
+    fn narrow_terminals_still_have_room_for_bytes() {
+        assert_eq!(row_width(80, 7), 73);
+        assert_eq!(row_width(10, 7 + 9), MIN_ROW_WIDTH);
+    }
+}
@@ -0,0 +1,104 @@
+//! Finding characters that could be mistaken for other characters, like a Cyrillic `а` posing as a Latin `a`.
+//!
+//! This uses the confusables data and mixed-script detection from
+//! [Unicode Technical Standard #39](https://www.unicode.org/reports/tr39/).
+//! ```
+//! let bytes = "pаypal".as_bytes(); // The second letter is U+0430 CYRILLIC SMALL LETTER A
+//! let string = string_inspector::DecodedString::decode(bytes, encoding::all::UTF_8).unwrap();
+//! let report = string_inspector::confusables::analyse(&string);
+//!
+//! assert_eq!("paypal", report.skeleton);
+//! assert_eq!(vec![1], report.suspicious);
+//! ```
+use unicode_script::{Script, UnicodeScript};
+use unicode_security::{is_potential_mixed_script_confusable_char, skeleton, MixedScript};
+use crate::decoding::DecodedString;
+
+/// The results of checking a decoded string for confusable characters.
+pub struct Report {
+    /// The string with every character replaced by the prototype it can be confused with.
+    /// Strings that can be confused with each other have the same skeleton.
+    pub skeleton: String,
+    /// The scripts used in the string, in the order they first appear.
+    /// Characters shared between scripts, like digits and punctuation, are not included.
+    pub scripts: Vec<&'static str>,
+    /// Whether the string mixes scripts that aren't normally used together.
+    pub mixed_script: bool,
+    /// The indexes of atoms that look like characters from another script.
+    pub suspicious: Vec<usize>
+}
+
+impl Report {
+    /// Whether anything in the string could be mistaken for something else.
+    pub fn is_suspicious(&self) -> bool {
+        !self.suspicious.is_empty()
+    }
+}
+
+/// Check a decoded string for confusable characters.
+///
+/// A character is suspicious if it can be confused with a character from another script,
+/// and either the string mixes scripts, or the whole string can be confused with ASCII text.
+pub fn analyse(decoding: &DecodedString) -> Report {
+    let text = decoding.to_string();
+    let skeleton: String = skeleton(&text).collect();
+    let mixed_script = !text.as_str().is_single_script();
+    let looks_like_ascii = skeleton.is_ascii() && !text.is_ascii();
+
+    let mut scripts = Vec::new();
+    for character in text.chars() {
+        let script = character.script();
+        if script != Script::Common && script != Script::Inherited && script != Script::Unknown && !scripts.contains(&script.full_name()) {
+            scripts.push(script.full_name());
+        }
+    }
+
+    let suspicious = if mixed_script || looks_like_ascii {
+        decoding.atoms.iter()
+            .filter(|atom| atom.is_valid() && is_confusable(atom.to_char()))
+            .map(|atom| atom.index())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Report { skeleton, scripts, mixed_script, suspicious }
+}
+
+fn is_confusable(character: char) -> bool {
+    !character.is_ascii() && is_potential_mixed_script_confusable_char(character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::UTF_8;
+
+    fn analyse_text(text: &str) -> Report {
+        let decoding = DecodedString::decode(text.as_bytes(), UTF_8).unwrap();
+        analyse(&decoding)
+    }
+
+    #[test]
+    fn ordinary_text_is_not_suspicious() {
+        assert!(!analyse_text("paypal").is_suspicious());
+        assert!(!analyse_text("Привет").is_suspicious());
+        assert!(!analyse_text("café").is_suspicious());
+    }
+
+    #[test]
+    fn mixed_scripts() {
+        let report = analyse_text("pаypal");
+        assert!(report.mixed_script);
+        assert_eq!(report.scripts, vec!["Latin", "Cyrillic"]);
+        assert_eq!(report.suspicious, vec![1]);
+    }
+
+    #[test]
+    fn whole_script_confusable_with_ascii() {
+        let report = analyse_text("рау");
+        assert!(!report.mixed_script);
+        assert_eq!(report.skeleton, "pay");
+        assert_eq!(report.suspicious, vec![0, 1, 2]);
+    }
+}
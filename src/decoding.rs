@@ -1,147 +1,593 @@
 //! Things for decoding bytes into strings.
-use colored::*;
 use std::borrow::Cow;
+use std::cmp;
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::fmt;
 use encoding::types::EncodingRef;
+use unic_ucd_age::Age;
+use unicode_blocks::find_unicode_block;
+use unicode_general_category::get_general_category;
+use unicode_script::UnicodeScript;
+use crate::bom;
+use crate::graphemes::{self, Grapheme};
+use crate::render::{Renderer, TerminalRenderer};
 
 extern crate encoding;
 
-use encoding::{Encoding, EncoderTrap};
+use encoding::Encoding;
 
-const BYTE_DISPLAY_SIZE: u16 = 3;
+/// The order in which the bytes of a multi-byte code unit are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Big,
+    Little
+}
+
+/// The smallest unit of encoded text.
+///
+/// Code units are a single byte in UTF-8 and single byte encodings,
+/// two bytes in UTF-16, and four bytes in UTF-32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeUnit {
+    U8(u8),
+    U16(u16, Endianness),
+    U32(u32, Endianness)
+}
+
+impl CodeUnit {
+    /// Read a code unit from a slice of 1, 2 or 4 bytes.
+    ///
+    /// # Panics
+    /// Panics if the slice is any other length.
+    pub(crate) fn from_bytes(bytes: &[u8], endianness: Endianness) -> CodeUnit {
+        let value = match endianness {
+            Endianness::Big => bytes.iter().fold(0, |acc, &b| (acc << 8) | u32::from(b)),
+            Endianness::Little => bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | u32::from(b))
+        };
+
+        match bytes.len() {
+            1 => CodeUnit::U8(value as u8),
+            2 => CodeUnit::U16(value as u16, endianness),
+            4 => CodeUnit::U32(value, endianness),
+            n => panic!("Code units cannot be {} bytes long", n)
+        }
+    }
+
+    /// The numeric value of the code unit.
+    pub fn value(&self) -> u32 {
+        match *self {
+            CodeUnit::U8(v) => u32::from(v),
+            CodeUnit::U16(v, _) => u32::from(v),
+            CodeUnit::U32(v, _) => v
+        }
+    }
+
+    /// The number of bytes in the code unit.
+    pub fn size(&self) -> usize {
+        match self {
+            CodeUnit::U8(_) => 1,
+            CodeUnit::U16(_, _) => 2,
+            CodeUnit::U32(_, _) => 4
+        }
+    }
+
+    /// Convert to a vector of bytes, in the same order they appeared in the input.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            CodeUnit::U8(v) => vec![v],
+            CodeUnit::U16(v, Endianness::Big) => v.to_be_bytes().to_vec(),
+            CodeUnit::U16(v, Endianness::Little) => v.to_le_bytes().to_vec(),
+            CodeUnit::U32(v, Endianness::Big) => v.to_be_bytes().to_vec(),
+            CodeUnit::U32(v, Endianness::Little) => v.to_le_bytes().to_vec()
+        }
+    }
+}
+
+/// One of the unicode encoding forms, which are the only encodings with code units of more than one byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingForm {
+    Utf8,
+    Utf16(Endianness),
+    Utf32(Endianness)
+}
+
+impl EncodingForm {
+    /// Find out which encoding form an encoding is, or `None` if it isn't UTF-8, UTF-16 or UTF-32.
+    pub fn of(encoding: &dyn Encoding) -> Option<EncodingForm> {
+        match encoding.name() {
+            "utf-8" => Some(EncodingForm::Utf8),
+            "utf-16le" => Some(EncodingForm::Utf16(Endianness::Little)),
+            "utf-16be" => Some(EncodingForm::Utf16(Endianness::Big)),
+            "utf-32le" => Some(EncodingForm::Utf32(Endianness::Little)),
+            "utf-32be" => Some(EncodingForm::Utf32(Endianness::Big)),
+            _ => None
+        }
+    }
+
+    /// The number of bytes in each code unit.
+    pub fn code_unit_size(self) -> usize {
+        match self {
+            EncodingForm::Utf8 => 1,
+            EncodingForm::Utf16(_) => 2,
+            EncodingForm::Utf32(_) => 4
+        }
+    }
+
+    /// The order of the bytes in each code unit. This is always big endian for UTF-8, which has single byte code units.
+    pub fn endianness(self) -> Endianness {
+        match self {
+            EncodingForm::Utf8 => Endianness::Big,
+            EncodingForm::Utf16(endianness) | EncodingForm::Utf32(endianness) => endianness
+        }
+    }
+}
+
+/// The size in bytes of the code units used by `encoding`, and their byte order.
+///
+/// Encodings other than UTF-16 and UTF-32 have single byte code units.
+pub fn code_unit_layout(encoding: &dyn Encoding) -> (usize, Endianness) {
+    EncodingForm::of(encoding).map_or((1, Endianness::Big), |form| (form.code_unit_size(), form.endianness()))
+}
+
+/// Split bytes into the code units used by `encoding`.
+///
+/// If the bytes don't divide evenly into code units, the leftover bytes at the end
+/// are returned as single byte code units.
+fn code_units(bytes: &[u8], encoding: &dyn Encoding) -> Vec<CodeUnit> {
+    let (size, endianness) = code_unit_layout(encoding);
+
+    let whole_units = bytes.len() - bytes.len() % size;
+    let mut units: Vec<CodeUnit> = bytes[..whole_units].chunks(size).map(|chunk| CodeUnit::from_bytes(chunk, endianness)).collect();
+    units.extend(bytes[whole_units..].iter().map(|&b| CodeUnit::U8(b)));
+    units
+}
+
+/// Add the characters decoded from `bytes` to the atoms.
+///
+/// Decoders normally output one character at a time, but if a sequence decodes to more
+/// than one character, the bytes belong to the first one, and the rest have no bytes of their own.
+fn push_characters(atoms: &mut Vec<Atom>, characters: &str, bytes: &[u8]) {
+    let mut bytes = Some(bytes);
+    for character in characters.chars() {
+        let bytes_for_character = bytes.take().unwrap_or_default().to_vec();
+        atoms.push(Atom::Character(DecodedCharacter { character, bytes: bytes_for_character, offset: 0, index: 0 }));
+    }
+}
+
+/// Add bytes that the decoder consumed without outputting a character to the last atom.
+///
+/// If there is no character to add them to, they are treated as invalid code units.
+fn push_leftovers(atoms: &mut Vec<Atom>, bytes: &[u8], encoding: &dyn Encoding) {
+    if bytes.is_empty() {
+        return;
+    }
+
+    match atoms.last_mut() {
+        Some(Atom::Character(c)) | Some(Atom::ByteOrderMark(c)) => c.bytes.extend_from_slice(bytes),
+        _ => atoms.extend(code_units(bytes, encoding).into_iter().map(Atom::invalid))
+    }
+}
 
 /// A logical character that has been decoded from some code points.
 #[derive(Debug, Clone)]
 pub struct DecodedCharacter {
     pub character: char,
-    pub bytes: Vec<u8>
+    pub bytes: Vec<u8>,
+    /// The position of the first byte in the input.
+    pub offset: usize,
+    /// The position of the atom in the decoded string.
+    pub index: usize
 }
 
 impl DecodedCharacter {
-    /// The number of columns required to format this character in the output.
-    fn width(&self) -> usize {
-        self.bytes.len() * BYTE_DISPLAY_SIZE as usize
+    /// The number of columns required to format this character with the default render options,
+    /// the same as [Atom::width](enum.Atom.html#method.width).
+    pub fn width(&self) -> usize {
+        Atom::Character(self.clone()).width()
     }
 
-    /// Convert a raw character into a DecodedCharacter using a particular Encoding.
-    ///
-    /// # Limitations
-    /// It's assumed that `encoding` is the same one used to decode the character.
-    /// We use this to reencode the character, in order to work out which code units
-    /// within the string actually belong to this character. This allows us to display bytes
-    /// and characters/unicode code points side by side. However, if the input is a unicode replacement
-    /// character, that means that there were code points in the input which could not be decoded,
-    /// and this method won't be able to recover those.
+    /// The unicode name of the character, like "LATIN SMALL LETTER E WITH ACUTE".
     ///
-    /// # Panics
-    /// Panics if character is unrepresentable in the provided encoding,
-    /// and that encoding cannot encode a unicode replacement character (U+FFFD).
-    fn new(character: char, encoding: &dyn Encoding) -> DecodedCharacter {
-        let bytes_for_character = encoding.encode(&character.to_string(), EncoderTrap::Replace).unwrap();
-        DecodedCharacter { character, bytes: bytes_for_character }
+    /// Control characters and unassigned code points don't have names.
+    pub fn name(&self) -> Option<String> {
+        unicode_names2::name(self.character).map(|name| name.to_string())
     }
 
-    /// Format the character in an easy to understand way.
-    /// ASCII characters are rendered normally.
-    /// Tabs, carriage returns and newlines are represented as escape sequences.
-    /// All other characters are rendered as their unicode codepoints.
+    /// The abbreviated general category of the character, like "Ll" for a lowercase letter.
+    pub fn general_category(&self) -> &'static str {
+        get_general_category(self.character).abbreviation()
+    }
+
+    /// The script the character belongs to, like "Latin", or "Common" for characters used by many scripts.
+    pub fn script(&self) -> &'static str {
+        self.character.script().full_name()
+    }
+
+    /// The block of code points the character belongs to, like "Latin-1 Supplement".
+    pub fn block(&self) -> Option<&'static str> {
+        find_unicode_block(self.character).map(|block| block.name())
+    }
+
+    /// The version of unicode that introduced the character, like "1.1".
     ///
     /// # Limitations
-    /// This is not guaranteed to work properly if the codepoint in hex is longer than the number of
-    /// bytes used to represent it in the encoding; for example, latin characters in UTF-16.
-    fn format_character(&self) -> String {
-        let char_size = self.width();
-        let character = self.character;
-
-        match character {
-            '\t' | '\r' | '\n' => {
-                let escaped = character.escape_default();
-                format!("{:width$} ", escaped, width = char_size)
-            }
-            '\u{20}'...'\u{7e}' => {
-                format!("{:width$}", character, width = char_size)
-            }
-            _ => {
-                let codepoint = format!("{:02x} ", character as u32);
-                format!("{:width$}", codepoint, width = char_size)
+    /// This only knows about characters up to unicode 10.0, and returns `None` for anything newer.
+    pub fn age(&self) -> Option<String> {
+        Age::of(self.character).map(|age| {
+            let version = age.actual();
+            format!("{}.{}", version.major, version.minor)
+        })
+    }
+}
+
+/// A code unit that could not be decoded, and where it appeared in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidCodeUnit {
+    pub code_unit: CodeUnit,
+    /// The position of the first byte in the input.
+    pub offset: usize,
+    /// The position of the atom in the decoded string.
+    pub index: usize,
+    /// What the decoder outputs in its place.
+    pub replacement: Replacement,
+    /// Why it could not be decoded.
+    pub reason: Reason
+}
+
+impl InvalidCodeUnit {
+    /// Explain what the decoder expected, and what it found instead.
+    pub fn explanation(&self) -> String {
+        let value = self.code_unit.value();
+        match self.reason {
+            Reason::UnexpectedContinuation => format!("expected a lead byte, found continuation byte {:02x}", value),
+            Reason::Overlong { length } => format!("expected the shortest encoding, found a {} byte sequence for a code point that fits in fewer bytes", length),
+            Reason::EncodedSurrogate => "expected a character, found the start of a surrogate (U+D800 to U+DFFF), which only exists in UTF-16".to_owned(),
+            Reason::OutOfRange if self.code_unit.size() == 4 => format!("expected a code point up to U+10FFFF, found {:x}", value),
+            Reason::OutOfRange => "expected a code point up to U+10FFFF, found the start of a higher one".to_owned(),
+            Reason::InvalidByte => format!("expected a byte that can appear in UTF-8, found {:02x}, which never does", value),
+            Reason::Truncated { expected, received, found } => {
+                let found = found.map_or_else(|| "the end of the input".to_owned(), |byte| format!("{:02x}", byte));
+                let plural = if received == 1 { "" } else { "s" };
+                format!("expected a {} byte sequence, found {} byte{} followed by {}", expected, received, plural, found)
             }
+            Reason::UnpairedSurrogate if value < 0xdc00 => format!("expected a low surrogate after high surrogate {:04x}, found none", value),
+            Reason::UnpairedSurrogate => format!("expected a high surrogate before low surrogate {:04x}, found none", value),
+            Reason::SurrogateCodePoint => format!("expected a character, found surrogate {:04x}, which only exists in UTF-16", value),
+            Reason::Unmapped => format!("expected a sequence the encoding has a character for, found {:02x}", value)
         }
     }
+}
 
-    /// Format the byte representation of the character using hex.
-    fn format_bytes(&self) -> String {
-        let mut buffer = String::new();
-        for byte in self.bytes.iter() {
-            let byte_hex = format!("{:02x} ", byte);
-            buffer.push_str(&byte_hex)
+/// Why a code unit could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// A UTF-8 continuation byte (80 to bf) without a lead byte before it.
+    UnexpectedContinuation,
+    /// Part of a UTF-8 sequence that is longer than it needs to be, like c0 80 for U+0000.
+    Overlong { length: usize },
+    /// Part of a UTF-8 encoding of a surrogate, like ed a0 80 for U+D800.
+    EncodedSurrogate,
+    /// Part of a sequence or code unit for a code point above U+10FFFF.
+    OutOfRange,
+    /// A byte that never appears in UTF-8 (f5 to ff).
+    InvalidByte,
+    /// Part of a sequence of `expected` bytes that was cut short after `received` bytes,
+    /// by a byte that can't continue it or by the end of the input.
+    Truncated { expected: usize, received: usize, found: Option<u8> },
+    /// Half of a UTF-16 surrogate pair without the other half.
+    UnpairedSurrogate,
+    /// A UTF-32 code unit in the surrogate range (U+D800 to U+DFFF).
+    SurrogateCodePoint,
+    /// A code unit or sequence that the encoding has no character for.
+    Unmapped
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Reason::UnexpectedContinuation => "unexpected continuation byte",
+            Reason::Overlong { .. } => "overlong encoding",
+            Reason::EncodedSurrogate => "encoded surrogate",
+            Reason::OutOfRange => "code point out of range",
+            Reason::InvalidByte => "invalid byte",
+            Reason::Truncated { .. } => "truncated sequence",
+            Reason::UnpairedSurrogate => "unpaired surrogate",
+            Reason::SurrogateCodePoint => "surrogate code point",
+            Reason::Unmapped => "unmapped"
+        };
+        f.write_str(description)
+    }
+}
+
+/// Work out why an ill-formed UTF-8 sequence was rejected, given `next`, the byte after it.
+fn utf8_reason(sequence: &[u8], next: Option<u8>) -> Reason {
+    let lead = sequence[0];
+    let expected = match lead {
+        0x80..=0xbf => return Reason::UnexpectedContinuation,
+        0xc0 | 0xc1 => return Reason::Overlong { length: 2 },
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return Reason::InvalidByte
+    };
+
+    // Some lead bytes restrict the range of the second byte, to rule out sequences that would be
+    // overlong, surrogates or out of range. Otherwise, the sequence just stopped too soon.
+    match (sequence.len(), lead, next) {
+        (1, 0xe0, Some(0x80..=0x9f)) => Reason::Overlong { length: 3 },
+        (1, 0xed, Some(0xa0..=0xbf)) => Reason::EncodedSurrogate,
+        (1, 0xf0, Some(0x80..=0x8f)) => Reason::Overlong { length: 4 },
+        (1, 0xf4, Some(0x90..=0xbf)) => Reason::OutOfRange,
+        _ => Reason::Truncated { expected, received: sequence.len(), found: next }
+    }
+}
+
+/// What a decoder outputs in place of an invalid code unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// The replacement character U+FFFD.
+    ReplacementCharacter,
+    /// Nothing, because the code unit is skipped, or is part of a sequence that has already been replaced.
+    Omitted,
+    /// A lone surrogate from U+DC80 to U+DCFF for each byte, like Python's `surrogateescape` error handler.
+    SurrogateEscape
+}
+
+/// What to do with input that can't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorHandling {
+    /// Stop at the first error, like Python's `strict`, Java's `CodingErrorAction.REPORT`,
+    /// or a browser `TextDecoder` with `fatal: true`.
+    Strict,
+    /// Replace invalid input with U+FFFD.
+    #[default]
+    Replace,
+    /// Skip invalid input, like Python's `ignore` or Java's `CodingErrorAction.IGNORE`.
+    Ignore,
+    /// Replace each invalid byte with a lone surrogate, like Python's `surrogateescape`,
+    /// so that the original bytes can be recovered when encoding again.
+    SurrogateEscape
+}
+
+/// How much invalid input each replacement character stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplacementUnit {
+    /// One replacement character for every invalid code unit.
+    #[default]
+    CodeUnit,
+    /// One replacement character for each maximal subpart of an ill-formed sequence: the longest
+    /// prefix of a valid sequence, or a single code unit. This is what the WHATWG encoding standard
+    /// (and so browsers), Python and Java do.
+    MaximalSubpart
+}
+
+/// Settings for [DecodedString::decode_with](struct.DecodedString.html#method.decode_with).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeOptions {
+    pub errors: ErrorHandling,
+    /// Only used when replacing errors.
+    pub replacement_unit: ReplacementUnit
+}
+
+impl DecodeOptions {
+    /// Make the atoms for an ill-formed sequence that the decoder reported, replaced according to the options.
+    ///
+    /// `next` is the byte after the sequence, if there is one, which is needed to explain why it is invalid.
+    fn invalid_atoms(&self, bytes: &[u8], offset: usize, next: Option<u8>, encoding: EncodingRef, cause: Cow<'static, str>) -> Result<Vec<Atom>, DecodeError> {
+        let replacement = match self.errors {
+            ErrorHandling::Strict => return Err(DecodeError::Invalid { encoding: encoding.name(), offset, cause }),
+            ErrorHandling::Replace => Replacement::ReplacementCharacter,
+            ErrorHandling::Ignore => Replacement::Omitted,
+            ErrorHandling::SurrogateEscape => {
+                // Only bytes that aren't ASCII can be escaped; Python treats anything else as an error
+                if let Some(ascii) = bytes.iter().position(u8::is_ascii) {
+                    return Err(DecodeError::Invalid { encoding: encoding.name(), offset: offset + ascii, cause: "ASCII bytes can't be escaped".into() });
+                }
+                Replacement::SurrogateEscape
+            }
+        };
+
+        let (size, _) = code_unit_layout(encoding);
+        let utf8_reason = if EncodingForm::of(encoding) == Some(EncodingForm::Utf8) { Some(utf8_reason(bytes, next)) } else { None };
+
+        let mut atoms: Vec<Atom> = code_units(bytes, encoding).into_iter().map(Atom::invalid).collect();
+        for (position, atom) in atoms.iter_mut().enumerate() {
+            if let Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) = atom {
+                let absorbed = position > 0 && self.replacement_unit == ReplacementUnit::MaximalSubpart;
+                u.replacement = if replacement == Replacement::ReplacementCharacter && absorbed { Replacement::Omitted } else { replacement };
+
+                // Single bytes left over in UTF-16 or UTF-32 are an incomplete code unit
+                if let Some(reason) = utf8_reason {
+                    u.reason = reason;
+                } else if size > 1 && u.code_unit.size() == 1 {
+                    u.reason = Reason::Truncated { expected: size, received: bytes.len() % size, found: None };
+                }
+            }
         }
-        buffer
+        Ok(atoms)
     }
 }
 
 // The result of decoding one or more code units
-// If there is a decoding error, we capture the first invalid code unit
+// If there is a decoding error, we capture each invalid code unit
 // and then continue decoding.
-// TODO: for UTF-16 a code unit is 2 bytes, not one byte, so this needs to
-// be more flexible.
 #[derive(Debug, Clone)]
 pub enum Atom {
     Character(DecodedCharacter),
-    InvalidCodeUnit(u8)
+    /// A U+FEFF character at the very start of the string, which identifies the encoding.
+    ByteOrderMark(DecodedCharacter),
+    InvalidCodeUnit(InvalidCodeUnit),
+    /// Half of a UTF-16 surrogate pair without the other half.
+    UnpairedSurrogate(InvalidCodeUnit),
+    /// A UTF-32 code unit in the surrogate range (U+D800 to U+DFFF), which is reserved for UTF-16.
+    SurrogateCodePoint(InvalidCodeUnit),
+    /// A UTF-32 code unit above U+10FFFF, the highest code point in unicode.
+    OutOfRange(InvalidCodeUnit)
 }
 
 impl Atom {
     /// Format the byte representation of the character using hex.
+    ///
+    /// This uses the default [TerminalRenderer](../render/struct.TerminalRenderer.html), without colours.
     pub fn format_bytes(&self) -> String {
-        match &self {
-            Atom::Character(c) => {c.format_bytes()}
-            Atom::InvalidCodeUnit(b) => {format!("{:02x} ", b)}
-        }
+        TerminalRenderer::default().render_bytes(self)
     }
 
-    /// Format the character in an easy to understand way.
-    /// ASCII characters are rendered normally.
-    /// Tabs, carriage returns and newlines are represented as escape sequences.
-    /// All other characters are rendered as their unicode codepoints.
-    ///
-    /// # Limitations
-    /// This is not guaranteed to work properly if the codepoint in hex is longer than the number of
-    /// bytes used to represent it in the encoding; for example, latin characters in UTF-16.
+    /// Format the character in an easy to understand way, as described for [TerminalRenderer](../render/struct.TerminalRenderer.html).
     pub fn format_character(&self) -> String {
-        match &self {
-            Atom::Character(c) => {c.format_character()}
-            Atom::InvalidCodeUnit(_) => {format!("\u{FFFD} ")}
-        }
+        TerminalRenderer::default().render_character(self)
     }
 
     /// Convert to the regular rust char type.
     pub fn to_char(&self) -> char {
         match &self {
-            Atom::Character(c) => {c.character}
-            Atom::InvalidCodeUnit(_) => {'\u{FFFD}'}
+            Atom::Character(c) | Atom::ByteOrderMark(c) => {c.character}
+            Atom::InvalidCodeUnit(_) | Atom::UnpairedSurrogate(_) | Atom::SurrogateCodePoint(_) | Atom::OutOfRange(_) => {'\u{FFFD}'}
         }
     }
 
     /// Convert to a vector of bytes.
     pub fn to_bytes(&self) -> Vec<u8> {
         match &self {
-            Atom::Character(c) => {c.bytes.clone()}
-            Atom::InvalidCodeUnit(b) => {vec![b.clone()]}
+            Atom::Character(c) | Atom::ByteOrderMark(c) => {c.bytes.clone()}
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => {u.code_unit.to_bytes()}
         }
     }
 
     /// The number of columns required to format this character in the output.
     pub fn width(&self) -> usize {
-        match & self {
-            Atom::Character(c) => {c.width()}
-            Atom::InvalidCodeUnit(_) => {2}
+        TerminalRenderer::default().width(self)
+    }
+
+    /// Whether the atom was decoded successfully.
+    pub fn is_valid(&self) -> bool {
+        match &self {
+            Atom::Character(_) | Atom::ByteOrderMark(_) => true,
+            Atom::InvalidCodeUnit(_) | Atom::UnpairedSurrogate(_) | Atom::SurrogateCodePoint(_) | Atom::OutOfRange(_) => false
+        }
+    }
+
+    /// The position of the first byte of the atom in the input.
+    pub fn offset(&self) -> usize {
+        match &self {
+            Atom::Character(c) | Atom::ByteOrderMark(c) => {c.offset}
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => {u.offset}
+        }
+    }
+
+    /// The position of the atom in the decoded string.
+    pub fn index(&self) -> usize {
+        match &self {
+            Atom::Character(c) | Atom::ByteOrderMark(c) => {c.index}
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => {u.index}
+        }
+    }
+
+    /// The range of bytes in the input that this atom was decoded from.
+    pub fn byte_range(&self) -> Range<usize> {
+        let length = match &self {
+            Atom::Character(c) | Atom::ByteOrderMark(c) => {c.bytes.len()}
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => {u.code_unit.size()}
+        };
+        self.offset()..self.offset() + length
+    }
+
+    fn set_position(&mut self, offset: usize, index: usize) {
+        match self {
+            Atom::Character(c) | Atom::ByteOrderMark(c) => {
+                c.offset = offset;
+                c.index = index;
+            }
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => {
+                u.offset = offset;
+                u.index = index;
+            }
+        }
+    }
+
+    /// What the decoder outputs in place of the atom, if it is invalid.
+    pub fn replacement(&self) -> Option<Replacement> {
+        match &self {
+            Atom::Character(_) | Atom::ByteOrderMark(_) => None,
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => Some(u.replacement)
+        }
+    }
+
+    /// Why the atom could not be decoded, if it is invalid.
+    pub fn reason(&self) -> Option<Reason> {
+        match &self {
+            Atom::Character(_) | Atom::ByteOrderMark(_) => None,
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => Some(u.reason)
+        }
+    }
+
+    /// What the decoder expected instead of the atom, if it is invalid. See [InvalidCodeUnit::explanation](struct.InvalidCodeUnit.html#method.explanation).
+    pub fn explanation(&self) -> Option<String> {
+        match &self {
+            Atom::Character(_) | Atom::ByteOrderMark(_) => None,
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => Some(u.explanation())
+        }
+    }
+
+    /// Wrap a code unit that could not be decoded.
+    fn invalid(code_unit: CodeUnit) -> Atom {
+        let invalid = |reason| InvalidCodeUnit { code_unit, offset: 0, index: 0, replacement: Replacement::ReplacementCharacter, reason };
+        match code_unit {
+            CodeUnit::U16(v, _) if (0xd800..=0xdfff).contains(&v) => Atom::UnpairedSurrogate(invalid(Reason::UnpairedSurrogate)),
+            CodeUnit::U32(v, _) if (0xd800..=0xdfff).contains(&v) => Atom::SurrogateCodePoint(invalid(Reason::SurrogateCodePoint)),
+            CodeUnit::U32(v, _) if v > 0x10ffff => Atom::OutOfRange(invalid(Reason::OutOfRange)),
+            _ => Atom::InvalidCodeUnit(invalid(Reason::Unmapped))
         }
     }
 }
 
+/// A problem the decoder reported and recovered from, by treating some bytes as invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The position of the first invalid byte in the input.
+    pub offset: usize,
+    /// The decoder's explanation, like "invalid sequence" or "incomplete sequence".
+    pub cause: Cow<'static, str>
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.cause, self.offset)
+    }
+}
+
+/// Why some bytes could not be decoded at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input is invalid, and the error handling is strict.
+    Invalid { encoding: &'static str, offset: usize, cause: Cow<'static, str> },
+    /// The decoder reported an error at a position past the end of the input.
+    /// This is a bug in the decoder rather than a problem with the input.
+    InvalidPosition { encoding: &'static str, position: isize, cause: Cow<'static, str> }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Invalid { encoding, offset, cause } => {
+                write!(f, "{} decoding failed at byte {}: {}", encoding, offset, cause)
+            }
+            DecodeError::InvalidPosition { encoding, position, cause } => {
+                write!(f, "the {} decoder reported \"{}\" at byte {}, which is outside the input", encoding, cause, position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 /// A string that has been decoded using a particular character encoding.
 pub struct DecodedString {
-    pub encoding: &'static dyn Encoding,
-    pub atoms: Vec<Atom>
+    pub encoding: EncodingRef,
+    pub atoms: Vec<Atom>,
+    /// Problems the decoder recovered from, in the order they appear in the input.
+    pub diagnostics: Vec<Diagnostic>
 }
 
 impl DecodedString {
@@ -149,29 +595,82 @@ impl DecodedString {
     ///
     /// Any characters that cannot be encoded will be represented using unicode replacement characters (U+FFFD).
     ///
+    /// The bytes of each atom are exactly the bytes it was decoded from, so concatenating
+    /// them gives back the original input. Bytes that the decoder consumes without producing
+    /// a character, like the escape sequences in ISO-2022-JP, belong to the next character,
+    /// or the previous one if there are no more characters.
+    ///
+    /// Invalid input is not an error: each problem the decoder reports is recorded in
+    /// [diagnostics](#structfield.diagnostics), and decoding carries on.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying decoder misbehaves, by reporting an error outside the input.
+    pub fn decode(string: &[u8], encoding: EncodingRef) -> Result<DecodedString, DecodeError> {
+        Self::decode_with(string, encoding, &DecodeOptions::default())
+    }
+
+    /// Decode a sequence of bytes, handling invalid input the way the options say.
+    ///
+    /// Invalid code units are always kept as atoms, so that their bytes can be shown,
+    /// but what they are replaced with depends on the options.
+    /// ```
+    /// use string_inspector::decoding::{DecodeOptions, DecodedString, ErrorHandling};
+    ///
+    /// let options = DecodeOptions { errors: ErrorHandling::Ignore, ..DecodeOptions::default() };
+    /// let string = DecodedString::decode_with(&[0x41, 0xff, 0x42], encoding::all::UTF_8, &options).unwrap();
+    ///
+    /// assert_eq!(3, string.atoms.len());
+    /// assert_eq!("AB", string.output());
+    /// ```
+    ///
     /// # Errors
-    /// Returns an error if anything goes wrong with the underlying decoder. This shouldn't actually happen(?)
-    pub fn decode(string: &[u8], encoding: EncodingRef) -> Result<DecodedString, Cow<'static, str>> {
+    /// Returns an error at the first invalid input if the error handling is strict, or if it can't
+    /// be escaped as surrogates. Also returns an error if the underlying decoder misbehaves.
+    pub fn decode_with(string: &[u8], encoding: EncodingRef, options: &DecodeOptions) -> Result<DecodedString, DecodeError> {
         let mut result = Vec::new();
+        let mut diagnostics = Vec::new();
         let mut decoder = encoding.raw_decoder();
-        let mut remaining = string;
         let mut string_writer = String::new();
 
-        loop {
-            let (offset, error) = decoder.raw_feed(remaining, &mut string_writer);
+        // The first byte that isn't part of an atom yet
+        let mut atom_start = 0;
+        // The first byte the decoder isn't finished with
+        let mut unprocessed = 0;
+        let mut position = 0;
 
-            // Consume the processed characters
-            let mut new_chars: Vec<Atom> = string_writer.chars().map(|c| Atom::Character(DecodedCharacter::new(c, encoding))).collect();
-            result.append(&mut new_chars);
-            string_writer.clear();
+        // Feed the decoder one byte at a time, so that whenever it outputs a character
+        // we know exactly which bytes it came from.
+        while position < string.len() {
+            let (processed, error) = decoder.raw_feed(&string[position..=position], &mut string_writer);
+            // An offset of zero means that the unprocessed bytes started before this byte
+            if processed > 0 {
+                unprocessed = position + processed;
+            }
 
-            if let Some(_) = error {
-                // Handle the first unprocessed code unit and shrink the input slice
-                result.push(Atom::InvalidCodeUnit(remaining[offset]));
-                let next = offset + 1;
-                remaining = &remaining[next..];
-            } else {
-                break;
+            if !string_writer.is_empty() {
+                push_characters(&mut result, &string_writer, &string[atom_start..unprocessed]);
+                string_writer.clear();
+                atom_start = unprocessed;
+            }
+
+            match error {
+                Some(codec_err) => {
+                    // Everything between the first unprocessed byte and `upto` is invalid.
+                    // Record each code unit separately and carry on from `upto`.
+                    let upto = cmp::max(position as isize + codec_err.upto, unprocessed as isize + 1);
+                    if upto as usize > string.len() {
+                        return Err(DecodeError::InvalidPosition { encoding: encoding.name(), position: upto, cause: codec_err.cause });
+                    }
+
+                    let upto = upto as usize;
+                    push_leftovers(&mut result, &string[atom_start..unprocessed], encoding);
+                    result.extend(options.invalid_atoms(&string[unprocessed..upto], unprocessed, string.get(upto).copied(), encoding, codec_err.cause.clone())?);
+                    diagnostics.push(Diagnostic { offset: unprocessed, cause: codec_err.cause });
+                    atom_start = upto;
+                    unprocessed = upto;
+                    position = upto;
+                }
+                None => position += 1
             }
         }
 
@@ -179,97 +678,180 @@ impl DecodedString {
         let error = decoder.raw_finish(&mut string_writer);
 
         if let Some(codec_err) = error {
-            eprintln!("Decoding error for {}: {}", encoding.name(), codec_err.cause);
+            // The `upto` attribute of CodecError is no use here, because raw_finish()
+            // has no current input for it to point into. Instead, the truncated character
+            // is everything the decoder was still holding on to.
+            push_leftovers(&mut result, &string[atom_start..unprocessed], encoding);
+            result.extend(options.invalid_atoms(&string[unprocessed..], unprocessed, None, encoding, codec_err.cause.clone())?);
+            diagnostics.push(Diagnostic { offset: unprocessed, cause: codec_err.cause });
+        } else {
+            push_leftovers(&mut result, &string[atom_start..], encoding);
+        }
 
-            // CodecError has an `upto` attribute which is supposed to point
-            // to the first remaining byte, relative to the *current input*
-            // When calling raw_finish() there is no current input.
-            // The docs say the return value is allowed to be negative, indicating
-            // the previous input, but the UTF-8/UTF-16 decoders always return 0:
-            // https://github.com/lifthrasiir/rust-encoding/blob/master/src/codec/utf_8.rs#L203
-            // https://github.com/lifthrasiir/rust-encoding/blob/master/src/codec/utf_16.rs#L253
-            // This means that if the input ended with a truncated character, we can't tell where
-            // it starts!
+        let mut offset = 0;
+        for (index, atom) in result.iter_mut().enumerate() {
+            atom.set_position(offset, index);
+            offset = atom.byte_range().end;
+        }
 
-            // For now, let's just assume that the last byte is bad.
-            // This will break for larger code sequences.
-            let end = remaining.len() - 1;
-            result.push(Atom::InvalidCodeUnit(remaining[end]));
+        // A leading U+FEFF is a byte order mark rather than part of the text
+        if let Some(Atom::Character(c)) = result.first() {
+            if c.character == bom::BYTE_ORDER_MARK {
+                result[0] = Atom::ByteOrderMark(c.clone());
+            }
         }
 
-        Ok(DecodedString {encoding: encoding, atoms: result})
+        Ok(DecodedString {encoding, atoms: result, diagnostics})
     }
 
     /// Format the byte representation of the string using hex.
+    ///
+    /// This uses the default [TerminalRenderer](../render/struct.TerminalRenderer.html);
+    /// use a [Renderer](../render/trait.Renderer.html) directly for other output.
     pub fn format_bytes(&self) -> String {
-        self.toggle_color(self.atoms.iter().map(Atom::format_bytes))
+        TerminalRenderer::default().bytes_row(&self.atoms)
     }
 
-    /// Format the string in an easy to understand way.
+    /// Format the string in an easy to understand way, lined up with [format_bytes](#method.format_bytes).
     /// ASCII characters are rendered normally.
     /// Tabs, carriage returns and newlines are represented as escape sequences.
     /// All other characters are rendered as their unicode codepoints.
-    ///
-    /// # Limitations
-    /// This is not guaranteed to work properly if codepoints in hex are longer than the number of
-    /// bytes used to represent it in the encoding; for example, latin characters in UTF-16.
     pub fn format_characters(&self) -> String {
-        self.toggle_color(self.atoms.iter().map(Atom::format_character))
+        TerminalRenderer::default().characters_row(&self.atoms)
     }
 
-    fn toggle_color<I>(&self, iterator: I) -> String
-    where I: Iterator<Item = String>
-    {
-        let mut color_toggle = true;
-        let mut buffer = String::new();
+    /// Format a bracket under each grapheme cluster, lined up with [format_bytes](#method.format_bytes).
+    pub fn format_graphemes(&self) -> String {
+        TerminalRenderer::default().graphemes_row(self)
+    }
 
-        for string in iterator {
-            if color_toggle {
-                buffer.push_str(&string.green().to_string());
-            } else {
-                buffer.push_str(&string.blue().to_string());
+    /// The text that a decoder with the same options would output, with invalid input replaced, skipped or escaped.
+    ///
+    /// Lone surrogates can't be stored in a `String`, so escaped bytes are written as escape sequences like `\udcff`.
+    pub fn output(&self) -> String {
+        let mut output = String::new();
+        for atom in self.atoms.iter() {
+            match atom.replacement() {
+                None => output.push(atom.to_char()),
+                Some(Replacement::ReplacementCharacter) => output.push('\u{FFFD}'),
+                Some(Replacement::Omitted) => {}
+                Some(Replacement::SurrogateEscape) => {
+                    for byte in atom.to_bytes() {
+                        output.push_str(&format!("\\u{:04x}", 0xdc00 + u32::from(byte)));
+                    }
+                }
             }
-            color_toggle = !color_toggle;
         }
-        buffer
+        output
     }
 
-    /// Convert to a regular string.
-    pub fn to_string(&self) -> String {
-        self.atoms.iter().map(Atom::to_char).collect()
+    /// Check for byte order marks that contradict the encoding or appear in the middle of the string.
+    pub fn byte_order_mark_warnings(&self) -> Vec<bom::Warning> {
+        bom::warnings(self)
+    }
+
+    /// Group the atoms into grapheme clusters, which are what a reader would think of as single characters.
+    pub fn graphemes(&self) -> Vec<Grapheme<'_>> {
+        graphemes::segment(self)
+    }
+
+    /// Find the atom containing a particular byte of the input.
+    pub fn atom_at_byte(&self, offset: usize) -> Option<&Atom> {
+        let position = self.atoms.binary_search_by(|atom| {
+            let range = atom.byte_range();
+            if range.end <= offset {
+                Ordering::Less
+            } else if range.start > offset {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+
+        position.ok().map(|index| &self.atoms[index])
+    }
+
+    /// The range of bytes in the input that an atom was decoded from.
+    ///
+    /// Characters that the decoder output along with an earlier one have no bytes of their own,
+    /// so they share the range of that one.
+    pub fn byte_range(&self, atom_index: usize) -> Option<Range<usize>> {
+        let atoms = self.atoms.get(..=atom_index)?;
+        atoms.iter().rev().map(Atom::byte_range).find(|range| !range.is_empty())
+            .or_else(|| atoms.last().map(Atom::byte_range))
     }
 
     /// Split into chunks so that the output of [format_bytes](#method.format_bytes) and [format_characters](#method.format_characters)
     /// fit within `max_line_width` characters for each chunk.
+    ///
+    /// Grapheme clusters are kept on the same line, unless they are too wide to fit on a line by themselves.
     pub fn wrap_lines(&self, max_line_width: usize) -> Vec<DecodedString> {
+        self.wrap_lines_with(max_line_width, Atom::width)
+    }
+
+    /// Split into chunks that fit within `max_line_width` columns, when each atom takes up `width` columns.
+    pub fn wrap_lines_with<F>(&self, max_line_width: usize, width: F) -> Vec<DecodedString>
+    where F: Fn(&Atom) -> usize
+    {
         let mut lines = Vec::new();
         let mut characters_in_line = Vec::new();
         let mut line_size = 0;
 
-        for character in self.atoms.iter() {
-            let char_output_width = character.width();
-            if line_size + char_output_width > max_line_width as usize {
-                lines.push(DecodedString {atoms: characters_in_line, encoding: self.encoding});
-                characters_in_line = Vec::new();
-                line_size = 0;
-            }
+        for grapheme in self.graphemes() {
+            let grapheme_width: usize = grapheme.atoms.iter().map(&width).sum();
+            let groups: Vec<&[Atom]> = if grapheme_width > max_line_width {
+                grapheme.atoms.chunks(1).collect()
+            } else {
+                vec![grapheme.atoms]
+            };
+
+            for group in groups {
+                let group_output_width: usize = group.iter().map(&width).sum();
+                if line_size + group_output_width > max_line_width && !characters_in_line.is_empty() {
+                    lines.push(self.line(characters_in_line));
+                    characters_in_line = Vec::new();
+                    line_size = 0;
+                }
 
-            characters_in_line.push(character.clone());
-            line_size += character.width();
+                characters_in_line.extend_from_slice(group);
+                line_size += group_output_width;
+            }
         }
 
-        if characters_in_line.len() > 0 {
-            lines.push(DecodedString {atoms: characters_in_line, encoding: self.encoding});
+        if !characters_in_line.is_empty() {
+            lines.push(self.line(characters_in_line));
         }
 
         lines
     }
+
+    /// Make a decoded string from some of the atoms, keeping the diagnostics that apply to them.
+    fn line(&self, atoms: Vec<Atom>) -> DecodedString {
+        let start = atoms.first().map_or(0, |atom| atom.offset());
+        let end = atoms.last().map_or(0, |atom| atom.byte_range().end);
+        // The diagnostics are in order, so the ones for this line can be found without looking at the rest
+        let first = self.diagnostics.partition_point(|diagnostic| diagnostic.offset < start);
+        let last = self.diagnostics.partition_point(|diagnostic| diagnostic.offset < end);
+        let diagnostics = self.diagnostics[first..cmp::max(first, last)].to_vec();
+        DecodedString {encoding: self.encoding, atoms, diagnostics}
+    }
+}
+
+impl fmt::Display for DecodedString {
+    /// Convert to a regular string.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for atom in self.atoms.iter() {
+            write!(f, "{}", atom.to_char())?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use encoding::all::UTF_8;
+    use encoding::all::{EUC_JP, GB18030, ISO_2022_JP, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+    use crate::utf_32::{UTF_32BE, UTF_32LE};
 
     #[test]
     fn ascii_printables() {
@@ -303,6 +885,26 @@ mod tests {
         assert_eq!(decoding.format_characters(), "e9    df    ");
     }
 
+    #[test]
+    fn unicode_properties() {
+        let decoding = DecodedString::decode("é🦀".as_bytes(), UTF_8).unwrap();
+        let (e, crab) = match (&decoding.atoms[0], &decoding.atoms[1]) {
+            (Atom::Character(e), Atom::Character(crab)) => (e, crab),
+            _ => panic!("expected two characters")
+        };
+
+        assert_eq!(e.name(), Some("LATIN SMALL LETTER E WITH ACUTE".to_owned()));
+        assert_eq!(e.general_category(), "Ll");
+        assert_eq!(e.script(), "Latin");
+        assert_eq!(e.block(), Some("Latin-1 Supplement"));
+        assert_eq!(e.age(), Some("1.1".to_owned()));
+
+        assert_eq!(crab.name(), Some("CRAB".to_owned()));
+        assert_eq!(crab.general_category(), "So");
+        assert_eq!(crab.script(), "Common");
+        assert_eq!(crab.age(), Some("8.0".to_owned()));
+    }
+
     #[test]
     fn overlong_utf8_code_units_are_not_decoded() {
         // The bytes C0 and C1 are not valid in UTF8.
@@ -389,25 +991,201 @@ mod tests {
         assert_eq!(decoding.format_characters(), "A  \u{FFFD} ");
     }
 
-    // This doesn't work yet.
     #[test]
-    #[ignore]
     fn bigger_truncated_character_is_invalid() {
         colored::control::set_override(false);
         let decoding = DecodedString::decode(&[0xF0, 0x9F, 0x92], UTF_8).unwrap();
-        assert_eq!(decoding.format_bytes(), "f0 9f 92");
+        assert_eq!(decoding.format_bytes(), "f0 9f 92 ");
         assert_eq!(decoding.format_characters(), "\u{FFFD} \u{FFFD} \u{FFFD} ");
     }
 
+    #[test]
+    fn truncated_character_after_regular_characters() {
+        let bytes = [0x41, 0x42, 0xF0, 0x9F, 0x92];
+        let decoding = DecodedString::decode(&bytes, UTF_8).unwrap();
+        let decoded_bytes: Vec<u8> = decoding.atoms.iter().flat_map(Atom::to_bytes).collect();
+        assert_eq!(decoded_bytes, bytes.to_vec());
+        assert_eq!(decoding.to_string(), "AB\u{FFFD}\u{FFFD}\u{FFFD}");
+    }
+
+    #[test]
+    fn truncated_utf16_surrogate_pair() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x41, 0x00, 0x3d, 0xd8, 0xa9], UTF_16LE).unwrap();
+        assert_eq!(decoding.format_bytes(), "41 00 3d d8 a9 ");
+        assert_eq!(decoding.format_characters(), "A     \u{FFFD}    \u{FFFD} ");
+    }
+
+    #[test]
+    fn utf16_code_units_are_two_bytes() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x41, 0x00, 0xac, 0x20], UTF_16LE).unwrap();
+        assert_eq!(decoding.format_bytes(), "41 00 ac 20 ");
+        assert_eq!(decoding.format_characters(), "A     20ac  ");
+    }
+
+    #[test]
+    fn utf16_surrogate_pairs_are_one_character() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0xd8, 0x3d, 0xdc, 0xa9], UTF_16BE).unwrap();
+        assert_eq!(decoding.atoms.len(), 1);
+        assert_eq!(decoding.format_bytes(), "d8 3d dc a9 ");
+        assert_eq!(decoding.format_characters(), "1f4a9       ");
+    }
+
+    #[test]
+    fn utf16_unpaired_high_surrogate() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x3d, 0xd8, 0x41, 0x00], UTF_16LE).unwrap();
+        assert_eq!(decoding.format_bytes(), "3d d8 41 00 ");
+        assert_eq!(decoding.format_characters(), "\u{FFFD}    A     ");
+
+        match decoding.atoms[0] {
+            Atom::UnpairedSurrogate(unit) => assert_eq!(unit.code_unit, CodeUnit::U16(0xd83d, Endianness::Little)),
+            ref other => panic!("Expected an unpaired surrogate, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn utf16_unpaired_low_surrogate() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x00, 0x41, 0xdc, 0xa9, 0x00, 0x42], UTF_16BE).unwrap();
+        assert_eq!(decoding.format_bytes(), "00 41 dc a9 00 42 ");
+        assert_eq!(decoding.format_characters(), "A     \u{FFFD}    B     ");
+
+        match decoding.atoms[1] {
+            Atom::UnpairedSurrogate(unit) => assert_eq!(unit.code_unit, CodeUnit::U16(0xdca9, Endianness::Big)),
+            ref other => panic!("Expected an unpaired surrogate, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn utf32_code_units_are_four_bytes() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x41, 0x00, 0x00, 0x00, 0xa9, 0xf4, 0x01, 0x00], UTF_32LE).unwrap();
+        assert_eq!(decoding.format_bytes(), "41 00 00 00 a9 f4 01 00 ");
+        assert_eq!(decoding.format_characters(), "A           1f4a9       ");
+    }
+
+    #[test]
+    fn utf32_surrogate_code_points_are_invalid() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x00, 0x00, 0xd8, 0x3d, 0x00, 0x00, 0x00, 0x41], UTF_32BE).unwrap();
+        assert_eq!(decoding.format_bytes(), "00 00 d8 3d 00 00 00 41 ");
+        assert_eq!(decoding.format_characters(), "\u{FFFD}          A           ");
+
+        match decoding.atoms[0] {
+            Atom::SurrogateCodePoint(unit) => assert_eq!(unit.code_unit, CodeUnit::U32(0xd83d, Endianness::Big)),
+            ref other => panic!("Expected a surrogate code point, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn utf32_cannot_escape_unicode_space() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x00, 0x00, 0x11, 0x00], UTF_32LE).unwrap();
+        assert_eq!(decoding.format_bytes(), "00 00 11 00 ");
+
+        match decoding.atoms[0] {
+            Atom::OutOfRange(unit) => assert_eq!(unit.code_unit.value(), 0x110000),
+            ref other => panic!("Expected an out of range code point, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn leading_byte_order_mark() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0xef, 0xbb, 0xbf, 0x41], UTF_8).unwrap();
+        assert_eq!(decoding.format_bytes(), "ef bb bf 41 ");
+        assert_eq!(decoding.format_characters(), "BOM      A  ");
+
+        match decoding.atoms[0] {
+            Atom::ByteOrderMark(ref c) => assert_eq!(c.bytes, vec![0xef, 0xbb, 0xbf]),
+            ref other => panic!("Expected a byte order mark, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn byte_order_mark_after_the_start_is_a_character() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0xff, 0xfe, 0xff, 0xfe], UTF_16LE).unwrap();
+        assert_eq!(decoding.format_characters(), "BOM   feff  ");
+    }
+
+    #[test]
+    fn escape_sequences_belong_to_the_next_character() {
+        // ESC $ B switches ISO-2022-JP to JIS X 0208, and ESC ( B switches back to ASCII
+        let bytes = [0x1b, 0x24, 0x42, 0x46, 0x7c, 0x1b, 0x28, 0x42];
+        let decoding = DecodedString::decode(&bytes, ISO_2022_JP).unwrap();
+        assert_eq!(decoding.to_string(), "日");
+        assert_eq!(decoding.atoms[0].to_bytes(), bytes.to_vec());
+    }
+
+    #[test]
+    fn atoms_contain_the_original_bytes() {
+        let text = "Hello, wörld! 日本語 한국어 Привет €";
+        let encodings: [EncodingRef; 6] = [UTF_8, UTF_16LE, WINDOWS_1252, EUC_JP, ISO_2022_JP, GB18030];
+        for &encoding in encodings.iter() {
+            let bytes = encoding.encode(text, encoding::EncoderTrap::Replace).unwrap();
+            let decoding = DecodedString::decode(&bytes, encoding).unwrap();
+            let decoded_bytes: Vec<u8> = decoding.atoms.iter().flat_map(Atom::to_bytes).collect();
+            assert_eq!(decoded_bytes, bytes, "{}", encoding.name());
+        }
+    }
+
+    #[test]
+    fn code_unit_bytes_preserve_endianness() {
+        assert_eq!(CodeUnit::U16(0xd83d, Endianness::Little).to_bytes(), vec![0x3d, 0xd8]);
+        assert_eq!(CodeUnit::U16(0xd83d, Endianness::Big).to_bytes(), vec![0xd8, 0x3d]);
+        assert_eq!(CodeUnit::from_bytes(&[0x3d, 0xd8], Endianness::Little), CodeUnit::U16(0xd83d, Endianness::Little));
+    }
+
+    #[test]
+    fn code_unit_layouts() {
+        assert_eq!(EncodingForm::of(UTF_16BE), Some(EncodingForm::Utf16(Endianness::Big)));
+        assert_eq!(code_unit_layout(UTF_32LE), (4, Endianness::Little));
+        assert_eq!(EncodingForm::of(WINDOWS_1252), None);
+        assert_eq!(code_unit_layout(WINDOWS_1252), (1, Endianness::Big));
+    }
+
+    #[test]
+    fn atoms_know_where_they_came_from() {
+        let decoding = DecodedString::decode(&[0x41, 0xc3, 0xa9, 0xff, 0x42], UTF_8).unwrap();
+        let offsets: Vec<usize> = decoding.atoms.iter().map(Atom::offset).collect();
+        let indexes: Vec<usize> = decoding.atoms.iter().map(Atom::index).collect();
+        assert_eq!(offsets, vec![0, 1, 3, 4]);
+        assert_eq!(indexes, vec![0, 1, 2, 3]);
+        assert_eq!(decoding.byte_range(1), Some(1..3));
+        assert_eq!(decoding.byte_range(4), None);
+    }
+
+    #[test]
+    fn find_atom_at_byte() {
+        let decoding = DecodedString::decode("aé💩".as_bytes(), UTF_8).unwrap();
+        assert_eq!(decoding.atom_at_byte(0).unwrap().to_char(), 'a');
+        assert_eq!(decoding.atom_at_byte(2).unwrap().to_char(), 'é');
+        assert_eq!(decoding.atom_at_byte(4).unwrap().to_char(), '💩');
+        assert_eq!(decoding.atom_at_byte(6).unwrap().to_char(), '💩');
+        assert!(decoding.atom_at_byte(7).is_none());
+    }
+
+    #[test]
+    fn line_wrapping_keeps_offsets() {
+        let decoding = DecodedString::decode("aaaaabbbbb".as_bytes(), UTF_8).unwrap();
+        let lines = decoding.wrap_lines(15);
+        assert_eq!(lines[1].atoms[0].offset(), 5);
+        assert_eq!(lines[1].atoms[0].index(), 5);
+    }
+
     #[test]
     fn display_width_single_byte() {
-        let decoded_character = DecodedCharacter {character: 'a', bytes: "a".as_bytes().to_owned()};
+        let decoded_character = DecodedCharacter {character: 'a', bytes: "a".as_bytes().to_owned(), offset: 0, index: 0};
         assert_eq!(decoded_character.width(), 3);
     }
 
     #[test]
     fn display_width_two_bytes() {
-        let decoded_character = DecodedCharacter {character: 'ß', bytes: "ß".as_bytes().to_owned()};
+        let decoded_character = DecodedCharacter {character: 'ß', bytes: "ß".as_bytes().to_owned(), offset: 0, index: 0};
         assert_eq!(decoded_character.width(), 6);
     }
 
@@ -462,4 +1240,198 @@ mod tests {
         assert_eq!(lines[2].format_bytes(), "63 63 ");
         assert_eq!(lines[2].format_characters(), "c  c  ");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn line_wrapping_keeps_graphemes_together() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode("aaae\u{301}".as_bytes(), UTF_8).unwrap();
+        let lines = decoding.wrap_lines(15);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].format_bytes(), "61 61 61 ");
+        assert_eq!(lines[1].format_bytes(), "65 cc 81 ");
+        assert_eq!(lines[1].format_graphemes(), "└──────┘ ");
+    }
+
+    #[test]
+    fn decoder_errors_are_diagnostics() {
+        let decoding = DecodedString::decode(&[0xff, 0x41, 0xc3], UTF_8).unwrap();
+        assert_eq!(decoding.diagnostics, vec![
+            Diagnostic { offset: 0, cause: "invalid sequence".into() },
+            Diagnostic { offset: 2, cause: "incomplete sequence".into() }
+        ]);
+        assert_eq!(decoding.wrap_lines(6)[1].diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn wrapping_many_errors() {
+        let bytes: Vec<u8> = [0xff, 0x41].iter().cycle().take(200_000).copied().collect();
+        let decoding = DecodedString::decode(&bytes, UTF_8).unwrap();
+        let lines = decoding.wrap_lines(80);
+
+        assert_eq!(lines.iter().map(|line| line.diagnostics.len()).sum::<usize>(), 100_000);
+        for line in lines.iter() {
+            let range = line.atoms[0].offset()..line.atoms.last().unwrap().byte_range().end;
+            assert!(line.diagnostics.iter().all(|diagnostic| range.contains(&diagnostic.offset)));
+        }
+    }
+
+    /// A decoder that reports an error further on than the input it was given.
+    struct OverreachingDecoder;
+
+    impl encoding::types::RawDecoder for OverreachingDecoder {
+        fn from_self(&self) -> Box<dyn encoding::types::RawDecoder> {
+            Box::new(OverreachingDecoder)
+        }
+
+        fn raw_feed(&mut self, _input: &[u8], _output: &mut dyn encoding::types::StringWriter) -> (usize, Option<encoding::types::CodecError>) {
+            (0, Some(encoding::types::CodecError { upto: 10, cause: "overreached".into() }))
+        }
+
+        fn raw_finish(&mut self, _output: &mut dyn encoding::types::StringWriter) -> Option<encoding::types::CodecError> {
+            None
+        }
+    }
+
+    struct OverreachingEncoding;
+
+    impl Encoding for OverreachingEncoding {
+        fn name(&self) -> &'static str {
+            "overreaching"
+        }
+
+        fn raw_encoder(&self) -> Box<dyn encoding::types::RawEncoder> {
+            UTF_8.raw_encoder()
+        }
+
+        fn raw_decoder(&self) -> Box<dyn encoding::types::RawDecoder> {
+            Box::new(OverreachingDecoder)
+        }
+    }
+
+    /// A decoder that outputs "e" followed by a combining acute accent for the byte 01, and ASCII for anything else.
+    struct AccentingDecoder;
+
+    impl encoding::types::RawDecoder for AccentingDecoder {
+        fn from_self(&self) -> Box<dyn encoding::types::RawDecoder> {
+            Box::new(AccentingDecoder)
+        }
+
+        fn raw_feed(&mut self, input: &[u8], output: &mut dyn encoding::types::StringWriter) -> (usize, Option<encoding::types::CodecError>) {
+            for &byte in input {
+                if byte == 0x01 {
+                    output.write_str("e\u{301}");
+                } else {
+                    output.write_char(byte as char);
+                }
+            }
+            (input.len(), None)
+        }
+
+        fn raw_finish(&mut self, _output: &mut dyn encoding::types::StringWriter) -> Option<encoding::types::CodecError> {
+            None
+        }
+    }
+
+    struct AccentingEncoding;
+
+    impl Encoding for AccentingEncoding {
+        fn name(&self) -> &'static str {
+            "accenting"
+        }
+
+        fn raw_encoder(&self) -> Box<dyn encoding::types::RawEncoder> {
+            UTF_8.raw_encoder()
+        }
+
+        fn raw_decoder(&self) -> Box<dyn encoding::types::RawDecoder> {
+            Box::new(AccentingDecoder)
+        }
+    }
+
+    #[test]
+    fn characters_decoded_together_share_their_bytes() {
+        colored::control::set_override(false);
+        let decoding = DecodedString::decode(&[0x61, 0x01, 0x62], &AccentingEncoding).unwrap();
+        assert_eq!(decoding.to_string(), "ae\u{301}b");
+        assert_eq!(decoding.format_bytes(), "61 01     62 ");
+        assert_eq!(decoding.format_characters(), "a  e  301 b  ");
+        assert_eq!(decoding.byte_range(2), Some(1..2));
+        assert_eq!(decoding.atom_at_byte(1).unwrap().to_char(), 'e');
+
+        // The accent has no bytes of its own, but still needs room for its code point
+        match &decoding.atoms[2] {
+            Atom::Character(c) => assert_eq!((c.width(), decoding.atoms[2].width()), (4, 4)),
+            atom => panic!("expected the accent, found {:?}", atom)
+        }
+    }
+
+    #[test]
+    fn broken_decoders_are_errors() {
+        let error = DecodedString::decode(b"abc", &OverreachingEncoding).err().unwrap();
+        assert_eq!(error, DecodeError::InvalidPosition { encoding: "overreaching", position: 10, cause: "overreached".into() });
+        assert_eq!(error.to_string(), "the overreaching decoder reported \"overreached\" at byte 10, which is outside the input");
+    }
+
+    fn decode_with(bytes: &[u8], errors: ErrorHandling, replacement_unit: ReplacementUnit) -> Result<DecodedString, DecodeError> {
+        DecodedString::decode_with(bytes, UTF_8, &DecodeOptions { errors, replacement_unit })
+    }
+
+    #[test]
+    fn strict_decoding_stops_at_the_first_error() {
+        let error = decode_with(&[0x41, 0xe2, 0x82, 0x41], ErrorHandling::Strict, ReplacementUnit::CodeUnit).err().unwrap();
+        assert_eq!(error.to_string(), "utf-8 decoding failed at byte 1: invalid sequence");
+        assert!(decode_with(b"valid", ErrorHandling::Strict, ReplacementUnit::CodeUnit).is_ok());
+    }
+
+    #[test]
+    fn replacing_each_code_unit_or_each_maximal_subpart() {
+        let bytes = [0xe2, 0x82, 0x41, 0xf0, 0x9f, 0x92];
+        assert_eq!(decode_with(&bytes, ErrorHandling::Replace, ReplacementUnit::CodeUnit).unwrap().output(), "\u{FFFD}\u{FFFD}A\u{FFFD}\u{FFFD}\u{FFFD}");
+
+        let decoding = decode_with(&bytes, ErrorHandling::Replace, ReplacementUnit::MaximalSubpart).unwrap();
+        assert_eq!(decoding.output(), "\u{FFFD}A\u{FFFD}");
+        assert_eq!(decoding.atoms.len(), 6);
+        assert_eq!(decoding.atoms[1].replacement(), Some(Replacement::Omitted));
+    }
+
+    #[test]
+    fn ignoring_and_escaping_errors() {
+        let bytes = [0x61, 0xff, 0xc3];
+        assert_eq!(decode_with(&bytes, ErrorHandling::Ignore, ReplacementUnit::CodeUnit).unwrap().output(), "a");
+        assert_eq!(decode_with(&bytes, ErrorHandling::SurrogateEscape, ReplacementUnit::CodeUnit).unwrap().output(), "a\\udcff\\udcc3");
+
+        let error = DecodedString::decode_with(&[0x00, 0xd8, 0x41, 0x00], UTF_16LE, &DecodeOptions { errors: ErrorHandling::SurrogateEscape, ..DecodeOptions::default() });
+        assert_eq!(error.err().unwrap(), DecodeError::Invalid { encoding: "utf-16le", offset: 0, cause: "ASCII bytes can't be escaped".into() });
+    }
+
+    fn reasons(bytes: &[u8], encoding: EncodingRef) -> Vec<Reason> {
+        DecodedString::decode(bytes, encoding).unwrap().atoms.iter().filter_map(|atom| match atom {
+            Atom::InvalidCodeUnit(u) | Atom::UnpairedSurrogate(u) | Atom::SurrogateCodePoint(u) | Atom::OutOfRange(u) => Some(u.reason),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn reasons_for_invalid_utf8() {
+        assert_eq!(reasons(&[0xc0, 0x80], UTF_8), vec![Reason::Overlong { length: 2 }, Reason::UnexpectedContinuation]);
+        assert_eq!(reasons(&[0xe0, 0x80, 0x80], UTF_8)[0], Reason::Overlong { length: 3 });
+        assert_eq!(reasons(&[0xed, 0xa0, 0x80], UTF_8)[0], Reason::EncodedSurrogate);
+        assert_eq!(reasons(&[0xf4, 0x90, 0x80, 0x80], UTF_8)[0], Reason::OutOfRange);
+        assert_eq!(reasons(&[0xff], UTF_8), vec![Reason::InvalidByte]);
+        assert_eq!(reasons(&[0xf0, 0x9f, 0x92, 0x41], UTF_8), vec![Reason::Truncated { expected: 4, received: 3, found: Some(0x41) }; 3]);
+    }
+
+    #[test]
+    fn reasons_for_invalid_utf16_and_utf32() {
+        assert_eq!(reasons(&[0x00, 0xd8, 0x41, 0x00], UTF_16LE), vec![Reason::UnpairedSurrogate]);
+        assert_eq!(reasons(&[0x41, 0x00, 0x42], UTF_16LE), vec![Reason::Truncated { expected: 2, received: 1, found: None }]);
+        assert_eq!(reasons(&[0x00, 0x11, 0x00, 0x00], UTF_32BE), vec![Reason::OutOfRange]);
+
+        let decoding = DecodedString::decode(&[0x00, 0xdc], UTF_16LE).unwrap();
+        match &decoding.atoms[0] {
+            Atom::UnpairedSurrogate(u) => assert_eq!(u.explanation(), "expected a high surrogate before low surrogate dc00, found none"),
+            atom => panic!("expected an unpaired surrogate, found {:?}", atom)
+        }
+    }
+}
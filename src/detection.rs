@@ -0,0 +1,190 @@
+//! Guessing the encoding of some bytes.
+//!
+//! Every supported encoding is tried, and scored according to how plausible the result looks.
+//! ```
+//! let bytes = "Größe".as_bytes();
+//! let candidates = string_inspector::detection::detect(bytes);
+//! let (best, _score) = candidates[0];
+//!
+//! assert_eq!("utf-8", best.name());
+//! ```
+//!
+//! # Limitations
+//! The heuristics don't know anything about particular languages, so they are easily fooled
+//! by short inputs, or by text that is valid in several single byte encodings.
+use encoding::types::EncodingRef;
+use crate::bom;
+use crate::decoding::{code_unit_layout, Atom, DecodedString, Endianness};
+use crate::encodings;
+
+/// Only this many bytes are used to score each encoding, to keep detection fast on large inputs.
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Encodings that will happily decode anything, so they tell us nothing.
+const UNINFORMATIVE_ENCODINGS: &[&str] = &["replacement", "pua-mapped-binary"];
+
+/// Score every supported encoding against some bytes.
+///
+/// Scores are between 0 and 1, where 1 means the encoding was identified by a byte order mark.
+/// The result is sorted with the most likely encoding first.
+pub fn detect(bytes: &[u8]) -> Vec<(EncodingRef, f32)> {
+    let sample = &bytes[..bytes.len().min(SAMPLE_SIZE)];
+    let sniffed = bom::sniff(sample).map(|(encoding, _)| encoding.name());
+
+    let mut scores: Vec<(EncodingRef, f32)> = encodings::known_encodings().into_iter()
+        .map(|(encoding, _)| encoding)
+        .filter(|encoding| !UNINFORMATIVE_ENCODINGS.contains(&encoding.name()))
+        .filter_map(|encoding| {
+            let decoding = DecodedString::decode(sample, encoding).ok()?;
+            let score = if sniffed == Some(encoding.name()) {
+                1.0
+            } else {
+                score(sample, &decoding)
+            };
+            Some((encoding, score))
+        })
+        .collect();
+
+    // Sorting is stable, so ties keep the order of known_encodings(), which starts with UTF-8
+    scores.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+    scores
+}
+
+/// How plausible a decoding looks, between 0 and 1.
+fn score(bytes: &[u8], decoding: &DecodedString) -> f32 {
+    text_score(decoding) * byte_pattern_factor(bytes, decoding) * prior(decoding)
+}
+
+/// How much a decoding looks like real text, between 0 and 1, regardless of the bytes it came from.
+///
+/// Invalid code units, replacement characters, control characters and unusual symbols all lower the score.
+pub fn text_score(decoding: &DecodedString) -> f32 {
+    if decoding.atoms.is_empty() {
+        return 0.0;
+    }
+
+    let total = decoding.atoms.len() as f32;
+    let mut invalid = 0;
+    let mut controls = 0;
+    let mut suspicious = 0;
+    let mut previous: Option<char> = None;
+
+    for atom in decoding.atoms.iter() {
+        let character = atom.to_char();
+        match atom {
+            Atom::Character(_) if character == '\u{FFFD}' => invalid += 1,
+            Atom::Character(_) if is_unexpected_control(character) => controls += 1,
+            Atom::Character(_) if is_suspicious(character, previous) => suspicious += 1,
+            Atom::Character(_) | Atom::ByteOrderMark(_) => {}
+            _ => invalid += 1
+        }
+        previous = Some(character);
+    }
+
+    let validity = 1.0 - invalid as f32 / total;
+    let clean = 1.0 - controls as f32 / total;
+    let plausibility = 1.0 - 0.5 * suspicious as f32 / total;
+
+    validity * validity * clean * plausibility
+}
+
+/// Control characters other than whitespace rarely appear in text.
+fn is_unexpected_control(character: char) -> bool {
+    character.is_control() && !matches!(character, '\t' | '\n' | '\r')
+}
+
+/// Characters that are valid, but much more likely to come from decoding with the wrong encoding:
+/// non-ASCII symbols, private use characters, and capital letters in the middle of a word.
+fn is_suspicious(character: char, previous: Option<char>) -> bool {
+    if character.is_ascii() {
+        return false;
+    }
+
+    if ('\u{E000}'..='\u{F8FF}').contains(&character) {
+        return true;
+    }
+
+    if character.is_alphabetic() {
+        return character.is_uppercase() && previous.is_some_and(char::is_lowercase);
+    }
+
+    !(character.is_numeric() || character.is_whitespace())
+}
+
+/// Text in UTF-16 or UTF-32 almost always contains zero bytes, because of the padding around
+/// ASCII characters, and they are the most significant bytes of each code unit.
+/// Text in other encodings almost never contains zero bytes.
+fn byte_pattern_factor(bytes: &[u8], decoding: &DecodedString) -> f32 {
+    let (unit_size, endianness) = code_unit_layout(decoding.encoding);
+
+    let zero_positions: Vec<usize> = bytes.iter().enumerate()
+        .filter(|&(_, &b)| b == 0)
+        .map(|(i, _)| i % unit_size)
+        .collect();
+
+    if unit_size == 1 {
+        return if zero_positions.is_empty() { 1.0 } else { 0.75 };
+    }
+
+    if zero_positions.is_empty() {
+        return 0.5;
+    }
+
+    // The least significant byte is the first byte in little endian, and the last in big endian
+    let least_significant = if endianness == Endianness::Little { 0 } else { unit_size - 1 };
+    let padding = zero_positions.iter().filter(|&&position| position != least_significant).count();
+    0.5 + 0.5 * padding as f32 / zero_positions.len() as f32
+}
+
+/// A slight preference for the most widely used encodings, to break ties.
+fn prior(decoding: &DecodedString) -> f32 {
+    match decoding.encoding.name() {
+        "utf-8" => 1.0,
+        "windows-1252" => 0.98,
+        _ => 0.95
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{KOI8_R, UTF_16LE, WINDOWS_1252};
+    use encoding::{EncoderTrap, Encoding};
+
+    fn best_guess(bytes: &[u8]) -> &'static str {
+        detect(bytes)[0].0.name()
+    }
+
+    #[test]
+    fn ascii_is_utf8() {
+        assert_eq!(best_guess(b"hello world"), "utf-8");
+    }
+
+    #[test]
+    fn latin1_accents_are_windows_1252() {
+        let bytes = WINDOWS_1252.encode("café crème brûlée", EncoderTrap::Strict).unwrap();
+        assert_eq!(best_guess(&bytes), "windows-1252");
+    }
+
+    #[test]
+    fn utf16_is_recognised_without_a_byte_order_mark() {
+        let bytes = UTF_16LE.encode("hello world", EncoderTrap::Strict).unwrap();
+        assert_eq!(best_guess(&bytes), "utf-16le");
+    }
+
+    #[test]
+    fn cyrillic_in_koi8_r() {
+        let bytes = KOI8_R.encode("Привет, как дела?", EncoderTrap::Strict).unwrap();
+        let candidates = detect(&bytes);
+        let koi8_r = candidates.iter().position(|(e, _)| e.name() == "koi8-r").unwrap();
+        let windows_1252 = candidates.iter().position(|(e, _)| e.name() == "windows-1252").unwrap();
+        assert!(koi8_r < windows_1252);
+    }
+
+    #[test]
+    fn byte_order_mark_wins() {
+        let candidates = detect(&[0xfe, 0xff, 0x00, 0x41]);
+        assert_eq!(candidates[0].0.name(), "utf-16be");
+        assert_eq!(candidates[0].1, 1.0);
+    }
+}
@@ -0,0 +1,285 @@
+//! Comparing two decoded strings, and explaining why they differ.
+//!
+//! The atoms of the two strings are aligned using their characters, so that an insertion or
+//! deletion doesn't make everything after it look different.
+//! ```
+//! use string_inspector::diff::{self, Cause};
+//!
+//! let left = string_inspector::DecodedString::decode("café".as_bytes(), encoding::all::UTF_8).unwrap();
+//! let right = string_inspector::DecodedString::decode("cafe\u{301}".as_bytes(), encoding::all::UTF_8).unwrap();
+//! let divergence = diff::compare(&left, &right).first_divergence().unwrap();
+//!
+//! assert_eq!(3, divergence.left_offset);
+//! assert_eq!(Cause::Normalization, divergence.cause);
+//! ```
+use std::fmt;
+use unicode_security::skeleton;
+use crate::decoding::{Atom, DecodedString};
+use crate::normalization::Form;
+
+/// Above this many comparisons, strings aren't aligned, and the differing parts are compared as a whole.
+const MAX_ALIGNMENT_CELLS: usize = 1 << 22;
+
+/// How an atom of one string relates to the other string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Both atoms are the same character decoded from the same bytes.
+    Same { left: usize, right: usize },
+    /// Both atoms are the same character, but decoded from different bytes.
+    Reencoded { left: usize, right: usize },
+    /// The atom is only in the left string.
+    Removed { left: usize },
+    /// The atom is only in the right string.
+    Added { right: usize }
+}
+
+/// The most likely reason for a difference between two strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// The same characters, encoded differently.
+    Encoding,
+    /// The same text in different normalization forms.
+    Normalization,
+    /// Different kinds of whitespace, or invisible characters.
+    Whitespace,
+    /// Different characters that look the same.
+    Homoglyph,
+    /// Different text.
+    Text
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Cause::Encoding => "the same characters are encoded differently",
+            Cause::Normalization => "the same text is normalized differently",
+            Cause::Whitespace => "different whitespace or invisible characters",
+            Cause::Homoglyph => "different characters that look the same",
+            Cause::Text => "different text"
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// The first place where two strings differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// The position of the difference in the bytes of the left string.
+    pub left_offset: usize,
+    /// The position of the difference in the bytes of the right string.
+    pub right_offset: usize,
+    pub cause: Cause
+}
+
+/// The atoms of two strings, lined up with each other.
+pub struct Diff<'a> {
+    pub left: &'a DecodedString,
+    pub right: &'a DecodedString,
+    pub changes: Vec<Change>
+}
+
+impl<'a> Diff<'a> {
+    /// Whether the strings have the same characters and the same bytes.
+    pub fn is_same(&self) -> bool {
+        self.changes.iter().all(|change| matches!(change, Change::Same { .. }))
+    }
+
+    /// The indexes of the atoms in the left string that are different in the right string.
+    pub fn changed_left(&self) -> Vec<usize> {
+        self.changes.iter().filter_map(|change| match *change {
+            Change::Reencoded { left, .. } | Change::Removed { left } => Some(left),
+            _ => None
+        }).collect()
+    }
+
+    /// The indexes of the atoms in the right string that are different in the left string.
+    pub fn changed_right(&self) -> Vec<usize> {
+        self.changes.iter().filter_map(|change| match *change {
+            Change::Reencoded { right, .. } | Change::Added { right } => Some(right),
+            _ => None
+        }).collect()
+    }
+
+    /// Find the first difference and explain it.
+    ///
+    /// Returns `None` if the strings are the same.
+    pub fn first_divergence(&self) -> Option<Divergence> {
+        // Where the next atom of each string starts, for differences that only add or remove atoms
+        let mut left_offset = 0;
+        let mut right_offset = 0;
+
+        for (position, change) in self.changes.iter().enumerate() {
+            match *change {
+                Change::Same { left, right } => {
+                    left_offset = self.left.atoms[left].byte_range().end;
+                    right_offset = self.right.atoms[right].byte_range().end;
+                }
+                Change::Reencoded { left, right } => {
+                    return Some(Divergence {
+                        left_offset: self.left.atoms[left].offset(),
+                        right_offset: self.right.atoms[right].offset(),
+                        cause: Cause::Encoding
+                    });
+                }
+                Change::Removed { .. } | Change::Added { .. } => {
+                    let (removed, added) = self.differing_run(position);
+                    return Some(Divergence {
+                        left_offset: removed.first().map_or(left_offset, Atom::offset),
+                        right_offset: added.first().map_or(right_offset, Atom::offset),
+                        cause: classify(&removed, &added)
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The atoms removed and added in the run of changes starting at `position`.
+    fn differing_run(&self, position: usize) -> (Vec<Atom>, Vec<Atom>) {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+
+        for change in self.changes[position..].iter() {
+            match *change {
+                Change::Removed { left } => removed.push(self.left.atoms[left].clone()),
+                Change::Added { right } => added.push(self.right.atoms[right].clone()),
+                _ => break
+            }
+        }
+
+        (removed, added)
+    }
+}
+
+/// Line up the atoms of two strings.
+pub fn compare<'a>(left: &'a DecodedString, right: &'a DecodedString) -> Diff<'a> {
+    let changes = align(&left.atoms, &right.atoms).into_iter().map(|pair| match pair {
+        (Some(l), Some(r)) if left.atoms[l].to_bytes() == right.atoms[r].to_bytes() => Change::Same { left: l, right: r },
+        (Some(l), Some(r)) => Change::Reencoded { left: l, right: r },
+        (Some(l), None) => Change::Removed { left: l },
+        (None, Some(r)) => Change::Added { right: r },
+        (None, None) => unreachable!()
+    }).collect();
+
+    Diff { left, right, changes }
+}
+
+/// Pair up atoms with the same character, using the longest common subsequence.
+fn align(left: &[Atom], right: &[Atom]) -> Vec<(Option<usize>, Option<usize>)> {
+    let same = |l: usize, r: usize| left[l].to_char() == right[r].to_char();
+
+    let prefix = (0..left.len().min(right.len())).take_while(|&i| same(i, i)).count();
+    let suffix = (0..left.len().min(right.len()) - prefix)
+        .take_while(|&i| same(left.len() - 1 - i, right.len() - 1 - i))
+        .count();
+
+    let mut pairs: Vec<(Option<usize>, Option<usize>)> = (0..prefix).map(|i| (Some(i), Some(i))).collect();
+
+    let left_middle = prefix..left.len() - suffix;
+    let right_middle = prefix..right.len() - suffix;
+    let (n, m) = (left_middle.len(), right_middle.len());
+
+    if (n + 1) * (m + 1) > MAX_ALIGNMENT_CELLS {
+        pairs.extend(left_middle.map(|l| (Some(l), None)));
+        pairs.extend(right_middle.map(|r| (None, Some(r))));
+    } else {
+        // lengths[i][j] is the length of the common subsequence of the last n - i and m - j atoms
+        let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if same(prefix + i, prefix + j) {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && same(prefix + i, prefix + j) {
+                pairs.push((Some(prefix + i), Some(prefix + j)));
+                i += 1;
+                j += 1;
+            } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+                pairs.push((Some(prefix + i), None));
+                i += 1;
+            } else {
+                pairs.push((None, Some(prefix + j)));
+                j += 1;
+            }
+        }
+    }
+
+    pairs.extend((0..suffix).rev().map(|i| (Some(left.len() - 1 - i), Some(right.len() - 1 - i))));
+    pairs
+}
+
+/// Explain why some atoms were replaced with others.
+fn classify(removed: &[Atom], added: &[Atom]) -> Cause {
+    let removed_text: String = removed.iter().map(Atom::to_char).collect();
+    let added_text: String = added.iter().map(Atom::to_char).collect();
+
+    // Compatibility normalization turns most spaces into U+0020, so check for whitespace first
+    if removed_text.chars().chain(added_text.chars()).all(is_blank) {
+        Cause::Whitespace
+    } else if Form::Nfkd.apply(&removed_text) == Form::Nfkd.apply(&added_text) {
+        Cause::Normalization
+    } else if skeleton(&removed_text).eq(skeleton(&added_text)) {
+        Cause::Homoglyph
+    } else {
+        Cause::Text
+    }
+}
+
+/// Whitespace, and characters that take up no space at all.
+fn is_blank(character: char) -> bool {
+    character.is_whitespace() || matches!(character, '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{UTF_8, WINDOWS_1252};
+
+    fn divergence(left: &str, right: &str) -> Option<Divergence> {
+        let left = DecodedString::decode(left.as_bytes(), UTF_8).unwrap();
+        let right = DecodedString::decode(right.as_bytes(), UTF_8).unwrap();
+        compare(&left, &right).first_divergence()
+    }
+
+    #[test]
+    fn identical_strings() {
+        assert_eq!(divergence("hello", "hello"), None);
+    }
+
+    #[test]
+    fn insertions_dont_change_the_alignment() {
+        let left = DecodedString::decode(b"abcd", UTF_8).unwrap();
+        let right = DecodedString::decode(b"abXcd", UTF_8).unwrap();
+        let diff = compare(&left, &right);
+
+        assert!(diff.changed_left().is_empty());
+        assert_eq!(diff.changed_right(), vec![2]);
+        assert_eq!(diff.first_divergence(), Some(Divergence { left_offset: 2, right_offset: 2, cause: Cause::Text }));
+    }
+
+    #[test]
+    fn explains_the_cause() {
+        assert_eq!(divergence("a b", "a\u{a0}b").unwrap().cause, Cause::Whitespace);
+        assert_eq!(divergence("paypal", "pаypal").unwrap().cause, Cause::Homoglyph);
+        assert_eq!(divergence("ﬁne", "fine").unwrap().cause, Cause::Normalization);
+        assert_eq!(divergence("cat", "dog").unwrap().cause, Cause::Text);
+    }
+
+    #[test]
+    fn same_characters_in_different_encodings() {
+        let left = DecodedString::decode("xé".as_bytes(), UTF_8).unwrap();
+        let right = DecodedString::decode(&[0x78, 0xe9], WINDOWS_1252).unwrap();
+        let diff = compare(&left, &right);
+
+        assert_eq!(diff.changes, vec![Change::Same { left: 0, right: 0 }, Change::Reencoded { left: 1, right: 1 }]);
+        assert_eq!(diff.first_divergence(), Some(Divergence { left_offset: 1, right_offset: 1, cause: Cause::Encoding }));
+    }
+}
@@ -0,0 +1,153 @@
+//! Looking up encodings by name.
+//!
+//! Encodings can be found using any of the labels defined in the
+//! [WHATWG Encoding Standard](https://encoding.spec.whatwg.org/#names-and-labels),
+//! as well as the UTF-32 encodings from [utf_32](../utf_32/index.html)
+//! and code page 437 from [cp437](../cp437/index.html).
+//! ```
+//! let encoding = string_inspector::encodings::encoding_from_label("latin1").unwrap();
+//! assert_eq!("windows-1252", encoding.name());
+//! ```
+use encoding::types::EncodingRef;
+use crate::cp437;
+use crate::utf_32;
+
+/// Every label in the WHATWG Encoding Standard, grouped by encoding.
+const WHATWG_LABELS: &[&str] = &[
+    "unicode-1-1-utf-8", "utf-8", "utf8",
+    "866", "cp866", "csibm866", "ibm866",
+    "csisolatin2", "iso-8859-2", "iso-ir-101", "iso8859-2", "iso88592", "iso_8859-2",
+    "iso_8859-2:1987", "l2", "latin2",
+    "csisolatin3", "iso-8859-3", "iso-ir-109", "iso8859-3", "iso88593", "iso_8859-3",
+    "iso_8859-3:1988", "l3", "latin3",
+    "csisolatin4", "iso-8859-4", "iso-ir-110", "iso8859-4", "iso88594", "iso_8859-4",
+    "iso_8859-4:1988", "l4", "latin4",
+    "csisolatincyrillic", "cyrillic", "iso-8859-5", "iso-ir-144", "iso8859-5", "iso88595",
+    "iso_8859-5", "iso_8859-5:1988",
+    "arabic", "asmo-708", "csiso88596e", "csiso88596i", "csisolatinarabic", "ecma-114",
+    "iso-8859-6", "iso-8859-6-e", "iso-8859-6-i", "iso-ir-127", "iso8859-6", "iso88596",
+    "iso_8859-6", "iso_8859-6:1987",
+    "csisolatingreek", "ecma-118", "elot_928", "greek", "greek8", "iso-8859-7", "iso-ir-126",
+    "iso8859-7", "iso88597", "iso_8859-7", "iso_8859-7:1987", "sun_eu_greek",
+    "csiso88598e", "csisolatinhebrew", "hebrew", "iso-8859-8", "iso-8859-8-e", "iso-ir-138",
+    "iso8859-8", "iso88598", "iso_8859-8", "iso_8859-8:1988", "visual",
+    "csiso88598i", "iso-8859-8-i", "logical",
+    "csisolatin6", "iso-8859-10", "iso-ir-157", "iso8859-10", "iso885910", "l6", "latin6",
+    "iso-8859-13", "iso8859-13", "iso885913",
+    "iso-8859-14", "iso8859-14", "iso885914",
+    "csisolatin9", "iso-8859-15", "iso8859-15", "iso885915", "iso_8859-15", "l9",
+    "iso-8859-16",
+    "cskoi8r", "koi", "koi8", "koi8-r", "koi8_r",
+    "koi8-u",
+    "csmacintosh", "mac", "macintosh", "x-mac-roman",
+    "dos-874", "iso-8859-11", "iso8859-11", "iso885911", "tis-620", "windows-874",
+    "cp1250", "windows-1250", "x-cp1250",
+    "cp1251", "windows-1251", "x-cp1251",
+    "ansi_x3.4-1968", "ascii", "cp1252", "cp819", "csisolatin1", "ibm819", "iso-8859-1",
+    "iso-ir-100", "iso8859-1", "iso88591", "iso_8859-1", "iso_8859-1:1987", "l1", "latin1",
+    "us-ascii", "windows-1252", "x-cp1252",
+    "cp1253", "windows-1253", "x-cp1253",
+    "cp1254", "csisolatin5", "iso-8859-9", "iso-ir-148", "iso8859-9", "iso88599", "iso_8859-9",
+    "iso_8859-9:1989", "l5", "latin5", "windows-1254", "x-cp1254",
+    "cp1255", "windows-1255", "x-cp1255",
+    "cp1256", "windows-1256", "x-cp1256",
+    "cp1257", "windows-1257", "x-cp1257",
+    "cp1258", "windows-1258", "x-cp1258",
+    "x-mac-cyrillic", "x-mac-ukrainian",
+    "chinese", "csgb2312", "csiso58gb231280", "gb2312", "gb_2312", "gb_2312-80", "gbk", "iso-ir-58",
+    "x-gbk",
+    "gb18030",
+    "big5", "big5-hkscs", "cn-big5", "csbig5", "x-x-big5",
+    "cseucpkdfmtjapanese", "euc-jp", "x-euc-jp",
+    "csiso2022jp", "iso-2022-jp",
+    "csshiftjis", "ms_kanji", "shift-jis", "shift_jis", "sjis", "windows-31j", "x-sjis",
+    "cseuckr", "csksc56011987", "euc-kr", "iso-ir-149", "korean", "ks_c_5601-1987",
+    "ks_c_5601-1989", "ksc5601", "ksc_5601", "windows-949",
+    "csiso2022kr", "hz-gb-2312", "iso-2022-kr", "iso-2022-cn", "iso-2022-cn-ext",
+    "utf-16be",
+    "utf-16", "utf-16le",
+    "x-user-defined",
+];
+
+/// Labels for encodings that aren't part of the WHATWG standard.
+const EXTRA_LABELS: &[&str] = &["utf-32le", "utf-32be", "437", "cp437", "csibm437", "ibm437"];
+
+/// Labels are case insensitive and ignore surrounding whitespace.
+fn normalize(label: &str) -> String {
+    label.trim().to_ascii_lowercase()
+}
+
+/// Look up an encoding by one of its labels.
+pub fn encoding_from_label(label: &str) -> Option<EncodingRef> {
+    match &normalize(label)[..] {
+        "utf-32le" => Some(utf_32::UTF_32LE),
+        "utf-32be" => Some(utf_32::UTF_32BE),
+        "437" | "cp437" | "csibm437" | "ibm437" => Some(cp437::IBM437),
+        label => encoding::label::encoding_from_whatwg_label(label)
+    }
+}
+
+/// The preferred name for an encoding.
+pub fn canonical_name(encoding: EncodingRef) -> &'static str {
+    encoding.whatwg_name().unwrap_or_else(|| encoding.name())
+}
+
+/// Every supported encoding, along with all the labels that refer to it.
+pub fn known_encodings() -> Vec<(EncodingRef, Vec<&'static str>)> {
+    let mut known: Vec<(EncodingRef, Vec<&'static str>)> = Vec::new();
+
+    for &label in WHATWG_LABELS.iter().chain(EXTRA_LABELS.iter()) {
+        let encoding = encoding_from_label(label).unwrap();
+        match known.iter_mut().find(|(e, _)| e.name() == encoding.name()) {
+            Some((_, labels)) => labels.push(label),
+            None => known.push((encoding, vec![label]))
+        }
+    }
+
+    known
+}
+
+/// Find labels that look like a misspelling of `label`, closest first.
+pub fn suggestions(label: &str) -> Vec<&'static str> {
+    let label = normalize(label);
+    let mut candidates: Vec<(usize, &'static str)> = WHATWG_LABELS.iter().chain(EXTRA_LABELS.iter())
+        .map(|&candidate| (strsim::levenshtein(&label, candidate), candidate))
+        .filter(|&(distance, _)| distance <= 2)
+        .collect();
+
+    candidates.sort();
+    candidates.into_iter().take(3).map(|(_, candidate)| candidate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_case_insensitive() {
+        assert_eq!(encoding_from_label(" Shift_JIS ").unwrap().name(), "windows-31j");
+        assert_eq!(encoding_from_label("UTF-32LE").unwrap().name(), "utf-32le");
+    }
+
+    #[test]
+    fn every_label_is_known() {
+        for &label in WHATWG_LABELS.iter() {
+            assert!(encoding_from_label(label).is_some(), "{}", label);
+        }
+    }
+
+    #[test]
+    fn aliases_are_grouped_by_encoding() {
+        let known = known_encodings();
+        let (encoding, labels) = known.iter().find(|(e, _)| e.name() == "utf-8").unwrap();
+        assert_eq!(canonical_name(*encoding), "utf-8");
+        assert_eq!(labels, &vec!["unicode-1-1-utf-8", "utf-8", "utf8"]);
+    }
+
+    #[test]
+    fn suggests_similar_labels() {
+        assert_eq!(suggestions("utf9")[0], "utf8");
+        assert!(suggestions("shiftjis").contains(&"shift-jis"));
+        assert!(suggestions("klingon").is_empty());
+    }
+}
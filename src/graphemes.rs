@@ -0,0 +1,100 @@
+//! Grouping atoms into grapheme clusters, which are what a reader would think of as single characters.
+//!
+//! A grapheme cluster can be made of several code points, like a letter followed by combining
+//! accents, or a flag made of two regional indicators. Clusters follow the extended grapheme
+//! cluster rules from [UAX #29](https://www.unicode.org/reports/tr29/).
+//! ```
+//! let bytes = "e\u{301}x".as_bytes();
+//! let string = string_inspector::DecodedString::decode(bytes, encoding::all::UTF_8).unwrap();
+//! let graphemes = string.graphemes();
+//!
+//! assert_eq!(2, graphemes.len());
+//! assert_eq!(2, graphemes[0].atoms.len());
+//! assert_eq!(0..3, graphemes[0].byte_range());
+//! ```
+use std::fmt;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::decoding::{Atom, DecodedString};
+use crate::render::{Renderer, TerminalRenderer};
+
+/// A group of consecutive atoms that are displayed as a single character.
+#[derive(Debug, Clone, Copy)]
+pub struct Grapheme<'a> {
+    pub atoms: &'a [Atom]
+}
+
+impl<'a> Grapheme<'a> {
+    /// The range of bytes in the input that the grapheme was decoded from.
+    pub fn byte_range(&self) -> Range<usize> {
+        let start = self.atoms.first().map_or(0, |atom| atom.byte_range().start);
+        let end = self.atoms.last().map_or(start, |atom| atom.byte_range().end);
+        start..end
+    }
+
+    /// The number of columns required to format this grapheme in the output.
+    pub fn width(&self) -> usize {
+        self.atoms.iter().map(Atom::width).sum()
+    }
+
+    /// Format a bracket that spans the same columns as the bytes of the grapheme.
+    pub fn format_bracket(&self) -> String {
+        TerminalRenderer::default().render_grapheme(self)
+    }
+}
+
+impl<'a> fmt::Display for Grapheme<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for atom in self.atoms.iter() {
+            write!(f, "{}", atom.to_char())?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a decoded string into grapheme clusters.
+///
+/// Invalid code units are treated as replacement characters (U+FFFD).
+pub fn segment(decoding: &DecodedString) -> Vec<Grapheme<'_>> {
+    let text = decoding.to_string();
+    let mut graphemes = Vec::new();
+    let mut start = 0;
+
+    // Every atom is exactly one char, so clusters can be mapped back to atoms by counting chars
+    for cluster in text.graphemes(true) {
+        let end = start + cluster.chars().count();
+        graphemes.push(Grapheme { atoms: &decoding.atoms[start..end] });
+        start = end;
+    }
+
+    graphemes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::UTF_8;
+
+    fn clusters(text: &str) -> Vec<String> {
+        let decoding = DecodedString::decode(text.as_bytes(), UTF_8).unwrap();
+        segment(&decoding).iter().map(Grapheme::to_string).collect()
+    }
+
+    #[test]
+    fn combining_marks_join_the_previous_character() {
+        assert_eq!(clusters("ae\u{301}\u{302}"), vec!["a", "e\u{301}\u{302}"]);
+    }
+
+    #[test]
+    fn flags_are_one_grapheme() {
+        assert_eq!(clusters("🇬🇧🇫🇷"), vec!["🇬🇧", "🇫🇷"]);
+    }
+
+    #[test]
+    fn brackets_line_up_with_bytes() {
+        let decoding = DecodedString::decode("ae\u{301}".as_bytes(), UTF_8).unwrap();
+        let brackets: Vec<String> = segment(&decoding).iter().map(Grapheme::format_bracket).collect();
+        assert_eq!(brackets, vec!["└┘ ", "└──────┘ "]);
+        assert_eq!(segment(&decoding)[1].byte_range(), 1..4);
+    }
+}
@@ -0,0 +1,195 @@
+//! Rendering decoded strings as a self-contained HTML page, for sharing in bug reports.
+//!
+//! Each line of atoms becomes a table with a row of bytes and a row of characters, coloured
+//! the same way as the terminal output. Hovering over an atom shows its code point and position.
+//! ```
+//! let string = string_inspector::DecodedString::decode("é".as_bytes(), encoding::all::UTF_8).unwrap();
+//! let page = string_inspector::html::page(&[(None, 0, &[string])], &Default::default(), 80);
+//!
+//! assert!(page.starts_with("<!DOCTYPE html>"));
+//! assert!(page.contains("<td class=\"even\" title=\"U+00E9 LATIN SMALL LETTER E WITH ACUTE"));
+//! ```
+use crate::decoding::{Atom, DecodedString};
+use crate::graphemes::Grapheme;
+use crate::render::{RenderOptions, Renderer, TerminalRenderer};
+
+const STYLE: &str = "
+body { font-family: sans-serif; }
+table.atoms { border-collapse: collapse; font-family: monospace; margin-bottom: 0.5em; }
+table.atoms th { text-align: left; padding-right: 1em; color: #666; font-weight: normal; }
+table.atoms td { padding: 0 0.4em; text-align: left; white-space: pre; }
+td.even { color: #080; }
+td.odd { color: #00c; }
+td.invalid { background: #fdd; color: #c00; }
+td[title]:hover { background: #eee; }
+p.text { font-size: 1.2em; }
+";
+
+/// The number of columns used by the row labels.
+const LABEL_SIZE: usize = 7;
+
+/// Escape text for use in HTML content or attributes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character)
+        }
+    }
+    escaped
+}
+
+/// A description of an atom, shown when hovering over it.
+///
+/// `start` is added to the offset, for input that was read from part way through a file.
+fn tooltip(atom: &Atom, terminal: &TerminalRenderer, start: usize) -> String {
+    let description = match atom {
+        Atom::Character(c) | Atom::ByteOrderMark(c) => {
+            let name = c.name().unwrap_or_else(|| "<unnamed>".to_owned());
+            format!("U+{:04X} {}", c.character as u32, name)
+        }
+        Atom::InvalidCodeUnit(_) => "invalid code unit".to_owned(),
+        Atom::UnpairedSurrogate(_) => "unpaired surrogate".to_owned(),
+        Atom::SurrogateCodePoint(_) => "surrogate code point".to_owned(),
+        Atom::OutOfRange(_) => "code point out of range".to_owned()
+    };
+
+    let mut title = format!("{}\nbytes: {}\noffset: {}", description, terminal.render_bytes(atom).trim(), start + atom.offset());
+    if let (Some(reason), Some(explanation)) = (atom.reason(), atom.explanation()) {
+        title.push_str(&format!("\n{}: {}", reason, explanation));
+    }
+    title
+}
+
+/// Renders atoms as table cells, with a tooltip describing each atom.
+///
+/// Bytes and characters are formatted like the terminal output, and coloured by the stylesheet instead.
+#[derive(Debug, Clone, Default)]
+pub struct HtmlRenderer {
+    terminal: TerminalRenderer,
+    /// Added to the offsets in the tooltips.
+    start: usize
+}
+
+impl HtmlRenderer {
+    /// The colours in the options are ignored.
+    pub fn new(options: RenderOptions) -> Self {
+        HtmlRenderer { terminal: TerminalRenderer::new(RenderOptions { colors: None, ..options }), start: 0 }
+    }
+}
+
+impl Renderer for HtmlRenderer {
+    fn render_bytes(&self, atom: &Atom) -> String {
+        escape(self.terminal.render_bytes(atom).trim_end())
+    }
+
+    fn render_character(&self, atom: &Atom) -> String {
+        escape(self.terminal.render_character(atom).trim_end())
+    }
+
+    fn render_grapheme(&self, grapheme: &Grapheme) -> String {
+        escape(self.terminal.render_grapheme(grapheme).trim_end())
+    }
+
+    fn width(&self, atom: &Atom) -> usize {
+        self.terminal.width(atom)
+    }
+
+    fn decorate(&self, position: usize, atoms: &[Atom], output: String) -> String {
+        // Alternate from the start of each line, like the terminal output
+        let parity = if position % 2 == 0 { "even" } else { "odd" };
+        let class = if atoms.iter().all(Atom::is_valid) { parity.to_owned() } else { format!("{} invalid", parity) };
+        let title: Vec<String> = atoms.iter().map(|atom| tooltip(atom, &self.terminal, self.start)).collect();
+        format!("<td class=\"{}\" title=\"{}\">{}</td>", class, escape(&title.join("\n\n")), output)
+    }
+}
+
+/// Render the atoms of a decoded string as tables, one for each line that fits within `max_line_width` columns.
+///
+/// Bytes are written as the render options say, but their colours are ignored.
+/// The offsets in the tooltips start from `start`, like the offset gutter of the terminal output.
+pub fn tables(decoding: &DecodedString, render: &RenderOptions, start: usize, max_line_width: usize) -> String {
+    let renderer = HtmlRenderer { start, ..HtmlRenderer::new(render.clone()) };
+    let mut html = String::new();
+
+    for chunk in renderer.wrap_lines(decoding, max_line_width.saturating_sub(LABEL_SIZE)) {
+        html.push_str(&format!(
+            "<table class=\"atoms\">\n<tr><th>bytes</th>{}</tr>\n<tr><th>chars</th>{}</tr>\n</table>\n",
+            renderer.bytes_row(&chunk.atoms), renderer.characters_row(&chunk.atoms)
+        ));
+    }
+
+    html
+}
+
+/// Render a complete HTML page for some inputs, each with a label, the offset it starts from, and its decodings.
+pub fn page(inputs: &[(Option<&str>, usize, &[DecodedString])], render: &RenderOptions, max_line_width: usize) -> String {
+    let mut body = String::new();
+
+    for &(label, start, decodings) in inputs.iter() {
+        body.push_str("<section>\n");
+        if let Some(label) = label {
+            body.push_str(&format!("<h2>{}</h2>\n", escape(label)));
+        }
+
+        for decoding in decodings.iter() {
+            body.push_str(&format!("<h3>{}</h3>\n", escape(decoding.encoding.name())));
+            body.push_str(&tables(decoding, render, start, max_line_width));
+            body.push_str(&format!("<p class=\"text\">{}</p>\n", escape(&decoding.output())));
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>string-inspector</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        STYLE, body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::UTF_8;
+    use crate::decoding::{DecodeOptions, ErrorHandling};
+    use crate::render::Radix;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(escape("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn alternating_and_invalid_atoms() {
+        let decoding = DecodedString::decode(&[0x3c, 0x62, 0xff], UTF_8).unwrap();
+        let html = tables(&decoding, &RenderOptions::default(), 0, 80);
+
+        assert!(html.contains("<tr><th>bytes</th><td class=\"even\" title=\"U+003C LESS-THAN SIGN\nbytes: 3c\noffset: 0\">3c</td>"));
+        assert!(html.contains("<td class=\"odd\" title=\"U+0062 LATIN SMALL LETTER B\nbytes: 62\noffset: 1\">b</td>"));
+        assert!(html.contains("<td class=\"even invalid\" title=\"invalid code unit\nbytes: ff\noffset: 2\ninvalid byte: expected a byte that can appear in UTF-8, found ff, which never does\">\u{FFFD}</td>"));
+        assert!(html.contains("<tr><th>chars</th><td class=\"even\" title=\"U+003C LESS-THAN SIGN\nbytes: 3c\noffset: 0\">&lt;</td>"));
+    }
+
+    #[test]
+    fn bytes_follow_the_radix() {
+        let decoding = DecodedString::decode(&[0x3c, 0xff], UTF_8).unwrap();
+        let html = tables(&decoding, &RenderOptions { byte_radix: Radix::Decimal, ..RenderOptions::default() }, 0, 80);
+
+        assert!(html.contains("<tr><th>bytes</th><td class=\"even\" title=\"U+003C LESS-THAN SIGN\nbytes: 60\noffset: 0\"> 60</td>"));
+        assert!(html.contains("title=\"invalid code unit\nbytes: 255\noffset: 1"));
+    }
+
+    #[test]
+    fn text_and_offsets_match_the_terminal() {
+        let options = DecodeOptions { errors: ErrorHandling::Ignore, ..DecodeOptions::default() };
+        let decoding = DecodedString::decode_with(&[0x61, 0xff, 0x62], UTF_8, &options).unwrap();
+        let html = page(&[(None, 10, &[decoding])], &RenderOptions::default(), 80);
+
+        assert!(html.contains("<p class=\"text\">ab</p>"));
+        assert!(html.contains("title=\"U+0062 LATIN SMALL LETTER B\nbytes: 62\noffset: 12\""));
+    }
+}
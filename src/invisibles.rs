@@ -0,0 +1,162 @@
+//! Finding characters that take up no space, like zero width spaces, soft hyphens and variation selectors.
+//!
+//! These are either format characters (general category Cf) or default ignorable code points,
+//! which are not displayed unless the text is being edited, but still affect string comparisons.
+//! ```
+//! let bytes = "pass\u{200B}word".as_bytes();
+//! let string = string_inspector::DecodedString::decode(bytes, encoding::all::UTF_8).unwrap();
+//! let invisibles = string_inspector::invisibles::find(&string);
+//!
+//! assert_eq!(4, invisibles[0].offset);
+//! assert_eq!(b"password".to_vec(), string_inspector::invisibles::clean(&string));
+//! ```
+use encoding::EncoderTrap;
+use unicode_general_category::{get_general_category, GeneralCategory};
+use crate::decoding::{Atom, DecodedString};
+
+/// An invisible character in a decoded string.
+pub struct Invisible {
+    /// The position of the atom in the decoded string.
+    pub index: usize,
+    /// The position of the first byte of the character in the input.
+    pub offset: usize,
+    pub character: char,
+    /// The unicode name of the character, if it has one.
+    pub name: Option<String>,
+    /// What the character should be replaced with. This is empty if it should be removed,
+    /// and `None` if it should be kept because it is part of a sequence, like the joiners in an emoji.
+    pub replacement: Option<&'static str>
+}
+
+/// Whether a character is a format character or a default ignorable code point.
+pub fn is_invisible(character: char) -> bool {
+    get_general_category(character) == GeneralCategory::Format || is_default_ignorable(character)
+}
+
+/// The Default_Ignorable_Code_Point property from the unicode character database.
+fn is_default_ignorable(character: char) -> bool {
+    matches!(character,
+        '\u{00AD}' | '\u{034F}' | '\u{061C}' | '\u{115F}'..='\u{1160}' | '\u{17B4}'..='\u{17B5}' |
+        '\u{180B}'..='\u{180F}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{206F}' |
+        '\u{3164}' | '\u{FE00}'..='\u{FE0F}' | '\u{FEFF}' | '\u{FFA0}' | '\u{FFF0}'..='\u{FFF8}' |
+        '\u{1BCA0}'..='\u{1BCA3}' | '\u{1D173}'..='\u{1D17A}' | '\u{E0000}'..='\u{E0FFF}')
+}
+
+/// What an invisible character should be replaced with to clean up some text.
+fn replacement(character: char) -> &'static str {
+    match character {
+        // This used to be a space, and is still treated as one by some software
+        '\u{180E}' => " ",
+        _ => ""
+    }
+}
+
+/// Whether the invisible character at `position` in a grapheme cluster is part of a sequence,
+/// such as an emoji made of several others, so removing it would change how the text looks.
+fn in_sequence(grapheme: &[Atom], position: usize) -> bool {
+    // Anything attached to whitespace or the start of the text isn't modifying a visible character
+    let base = grapheme[0].to_char();
+    if position == 0 || base.is_whitespace() || is_invisible(base) {
+        return false;
+    }
+
+    match grapheme[position].to_char() {
+        // A zero width joiner only joins something if there is a character after it too
+        '\u{200D}' => position + 1 < grapheme.len(),
+        // Variation selectors and emoji tags modify the characters before them
+        '\u{FE00}'..='\u{FE0F}' | '\u{E0020}'..='\u{E007F}' | '\u{E0100}'..='\u{E01EF}' => true,
+        _ => false
+    }
+}
+
+/// Find every invisible character in a decoded string.
+///
+/// A byte order mark at the start is part of the encoding rather than the text, so it isn't included,
+/// but U+FEFF anywhere else is.
+pub fn find(decoding: &DecodedString) -> Vec<Invisible> {
+    let mut found = Vec::new();
+
+    for grapheme in decoding.graphemes() {
+        for (position, atom) in grapheme.atoms.iter().enumerate() {
+            if let Atom::Character(c) = atom {
+                if is_invisible(c.character) {
+                    let keep = in_sequence(grapheme.atoms, position);
+                    found.push(Invisible {
+                        index: c.index,
+                        offset: c.offset,
+                        character: c.character,
+                        name: c.name(),
+                        replacement: if keep { None } else { Some(replacement(c.character)) }
+                    });
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// The bytes of the input with every invisible character replaced or removed, except those that are part of a sequence.
+///
+/// Everything else, including invalid code units and the byte order mark at the start, is left as it was,
+/// so the result can be read back the same way as the input.
+pub fn clean(decoding: &DecodedString) -> Vec<u8> {
+    let found = find(decoding);
+    let mut found = found.iter().peekable();
+    let mut bytes = Vec::new();
+
+    for atom in decoding.atoms.iter() {
+        match found.peek() {
+            Some(invisible) if invisible.index == atom.index() => {
+                match invisible.replacement {
+                    Some(replacement) => bytes.extend(decoding.encoding.encode(replacement, EncoderTrap::Strict).unwrap_or_default()),
+                    None => bytes.extend(atom.to_bytes())
+                }
+                found.next();
+            }
+            _ => bytes.extend(atom.to_bytes())
+        }
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{UTF_8, UTF_16LE};
+
+    #[test]
+    fn finds_zero_width_and_format_characters() {
+        let decoding = DecodedString::decode("a\u{AD}b\u{200D}c\u{FE0F}\u{E0041}".as_bytes(), UTF_8).unwrap();
+        let found: Vec<char> = find(&decoding).iter().map(|invisible| invisible.character).collect();
+        assert_eq!(found, vec!['\u{AD}', '\u{200D}', '\u{FE0F}', '\u{E0041}']);
+    }
+
+    #[test]
+    fn visible_characters_are_ignored() {
+        let decoding = DecodedString::decode("a b\u{A0}c".as_bytes(), UTF_8).unwrap();
+        assert!(find(&decoding).is_empty());
+    }
+
+    #[test]
+    fn cleaning_keeps_the_encoding_and_invalid_bytes() {
+        // The byte order mark at the start is kept, but U+FEFF after "A" is removed
+        let decoding = DecodedString::decode(&[0xff, 0xfe, 0x41, 0x00, 0xff, 0xfe, 0x0b, 0x20, 0x0e, 0x18, 0x00, 0xd8], UTF_16LE).unwrap();
+        assert_eq!(clean(&decoding), vec![0xff, 0xfe, 0x41, 0x00, 0x20, 0x00, 0x00, 0xd8]);
+
+        let found: Vec<usize> = find(&decoding).iter().map(|invisible| invisible.index).collect();
+        assert_eq!(found, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn cleaning_keeps_emoji_sequences() {
+        // A woman technologist, a red heart with an emoji variation selector, and joiners and selectors that don't join anything
+        let text = "👩\u{200D}💻 \u{2764}\u{FE0F} a\u{200D}b \u{FE0F}c";
+        let decoding = DecodedString::decode(text.as_bytes(), UTF_8).unwrap();
+        assert_eq!(clean(&decoding), "👩\u{200D}💻 \u{2764}\u{FE0F} ab c".as_bytes().to_vec());
+
+        let replacements: Vec<Option<&str>> = find(&decoding).iter().map(|invisible| invisible.replacement).collect();
+        assert_eq!(replacements, vec![None, None, Some(""), Some("")]);
+    }
+}
@@ -11,6 +11,8 @@
 //! assert_eq!("\u{1b}[32m41 \u{1b}[0m\u{1b}[34m42 \u{1b}[0m\u{1b}[32m43 \u{1b}[0m", string.format_bytes());
 //! ```
 //!
+//! To format the output differently, such as without colours or with decimal bytes, use a [Renderer](render/trait.Renderer.html).
+//!
 //! [DecodedString](decoding/struct.DecodedString.html) contains a sequence of [Atoms](decoding/enum.Atom.html).
 //! Atoms represent either a valid character or an invalid code unit in the original string.
 //! ```
@@ -35,9 +37,32 @@
 //! assert_eq!('\u{FFFD}', string.atoms[3].to_char());
 //! assert_eq!(vec![0xC0], string.atoms[3].to_bytes());
 //! ```
+pub mod bidi;
+pub mod bits;
+pub mod bom;
 pub mod cli;
+pub mod confusables;
+pub mod cp437;
 pub mod decoding;
+pub mod detection;
+pub mod diff;
+pub mod encodings;
+pub mod graphemes;
+pub mod html;
+pub mod invisibles;
+pub mod mojibake;
+pub mod normalization;
+pub mod render;
+#[cfg(feature = "serde")]
+mod serialization;
+pub mod utf_32;
 
 pub use decoding::Atom;
+pub use decoding::CodeUnit;
 pub use decoding::DecodedCharacter;
-pub use decoding::DecodedString;
\ No newline at end of file
+pub use decoding::DecodeError;
+pub use decoding::DecodedString;
+pub use decoding::Diagnostic;
+pub use decoding::EncodingForm;
+pub use decoding::Endianness;
+pub use decoding::InvalidCodeUnit;
\ No newline at end of file
@@ -1,27 +1,194 @@
 use terminal_size::{Width, terminal_size};
 
 use string_inspector::DecodedString;
-use std::borrow::Cow;
+use string_inspector::decoding::DecodeOptions;
+use string_inspector::cli::Input;
+use std::io;
+use std::io::Write;
+use std::process;
 
 extern crate clap;
 
-fn main() {
-    let (encodings, buffer) = string_inspector::cli::parse_command_line();
+/// The exit status when some of the input could not be decoded.
+const DECODE_FAILURE: i32 = 2;
 
+fn main() {
+    // Decide on colours before parsing, because --list-encodings prints its output and exits straight away
     let size = terminal_size().map(|(Width(w), _)| w);
     if size.is_none() {
-        eprintln!("Unable to determine terminal size: wrapping output at 80 characters and disabling colors.");
         colored::control::set_override(false);
     }
 
+    let options = string_inspector::cli::parse_command_line();
+
+    if size.is_none() {
+        eprintln!("Unable to determine terminal size: wrapping output at 80 characters and disabling colors.");
+    }
+
     let size = size.unwrap_or(80) as usize;
 
-    let results: Vec<Result<DecodedString, Cow<'static, str>>> = encodings.into_iter().map(|encoding| DecodedString::decode(&buffer, encoding)).collect();
-    if results.iter().any(|result| result.is_err()) {
-        panic!("Unable to interpret input. This is a bug.");
+    if let Some((left, right)) = &options.diff {
+        let (left_decodings, left_succeeded) = decode_all(left, &options.decode);
+        let (right_decodings, right_succeeded) = decode_all(right, &options.decode);
+        exit_if_failed(left_succeeded && right_succeeded);
+
+        let (left_decoding, right_decoding) = (&left_decodings[0], &right_decodings[0]);
+        let diff = string_inspector::diff::compare(left_decoding, right_decoding);
+
+        let left_label = left.path.as_deref().unwrap_or("left");
+        let right_label = right.path.as_deref().unwrap_or("right");
+        string_inspector::cli::display_diff(&diff, left_label, right_label, &options.display.render, size);
+        process::exit(if diff.is_same() { 0 } else { 1 });
+    }
+
+    let mut succeeded = true;
+
+    if options.strip_invisibles {
+        for input in options.inputs.iter() {
+            let encoding = match input.encodings.first() {
+                Some(&encoding) => encoding,
+                None => {
+                    eprintln!("No encoding to decode {} with", input.path.as_deref().unwrap_or("the text"));
+                    succeeded = false;
+                    continue;
+                }
+            };
+
+            match DecodedString::decode_with(&input.buffer, encoding, &options.decode) {
+                Ok(decoding) => io::stdout().write_all(&string_inspector::invisibles::clean(&decoding)).expect("Unable to write to stdout"),
+                Err(err) => {
+                    string_inspector::cli::display_decode_error(input.path.as_deref(), &err);
+                    succeeded = false;
+                }
+            }
+        }
+        exit_if_failed(succeeded);
+        return;
+    }
+
+    #[cfg(feature = "serde")]
+    {
+        if options.format == string_inspector::cli::Format::Json {
+            let decoded: Vec<(&Input, Vec<DecodedString>)> = options.inputs.iter().map(|input| {
+                let (decodings, input_succeeded) = decode_all(input, &options.decode);
+                succeeded &= input_succeeded;
+                (input, decodings)
+            }).collect();
+            string_inspector::cli::display_json(&decoded);
+            exit_if_failed(succeeded);
+            return;
+        }
+    }
+
+    if options.format == string_inspector::cli::Format::Html {
+        let decoded: Vec<(&Input, Vec<DecodedString>)> = options.inputs.iter().map(|input| {
+            let (decodings, input_succeeded) = decode_all(input, &options.decode);
+            succeeded &= input_succeeded;
+            (input, decodings)
+        }).collect();
+        string_inspector::cli::display_html(&decoded, &options.display.render, size);
+        exit_if_failed(succeeded);
+        return;
     }
 
-    let decodings = results.into_iter().map(|result| result.unwrap()).collect();
+    let mut found_confusables = false;
+    let mut first = true;
+    for input in options.inputs.iter() {
+        if first {
+            first = false;
+        } else {
+            println!();
+        }
 
-    string_inspector::cli::display_decodings(&decodings, size)
-}
\ No newline at end of file
+        if let Some(path) = &input.path {
+            string_inspector::cli::display_file_header(path);
+        }
+
+        let (decodings, input_succeeded) = decode_all(input, &options.decode);
+        succeeded &= input_succeeded;
+
+        if !input.detected.is_empty() {
+            string_inspector::cli::display_detected_encodings(&input.detected, &decodings, size);
+        }
+
+        string_inspector::cli::display_decodings(&decodings, &options.display, size, input.start);
+
+        if options.mojibake {
+            for decoding in decodings.iter() {
+                println!();
+                string_inspector::cli::display_mojibake(decoding, &options.display, size, input.start);
+            }
+        }
+
+        if options.normalization {
+            for decoding in decodings.iter() {
+                println!();
+                string_inspector::cli::display_normalization(decoding);
+            }
+        }
+
+        if options.confusables {
+            for decoding in decodings.iter() {
+                println!();
+                let report = string_inspector::confusables::analyse(decoding);
+                string_inspector::cli::display_confusables(decoding, &report, &options.display.render, size);
+                found_confusables |= report.is_suspicious();
+            }
+        }
+
+        if options.bidi {
+            for decoding in decodings.iter() {
+                println!();
+                string_inspector::cli::display_bidi(decoding);
+            }
+        }
+
+        if options.invisibles {
+            for decoding in decodings.iter() {
+                println!();
+                string_inspector::cli::display_invisibles(decoding, options.display.offsets, input.start);
+            }
+        }
+
+        if options.bits {
+            for decoding in decodings.iter() {
+                println!();
+                string_inspector::cli::display_bits(decoding, &options.display.render);
+            }
+        }
+
+        if let Some(form) = options.normalize {
+            for decoding in decodings.iter() {
+                println!();
+                string_inspector::cli::display_normalized(decoding, form, &options.display, size);
+            }
+        }
+    }
+
+    exit_if_failed(succeeded);
+    if options.fail_on_confusables && found_confusables {
+        process::exit(1);
+    }
+}
+
+/// Decode an input with each of its encodings, reporting any that fail.
+///
+/// Returns the decodings that succeeded, and whether all of them did.
+fn decode_all(input: &Input, options: &DecodeOptions) -> (Vec<DecodedString>, bool) {
+    let mut succeeded = true;
+    let decodings = input.encodings.iter().filter_map(|&encoding| {
+        DecodedString::decode_with(&input.buffer, encoding, options).map_err(|err| {
+            string_inspector::cli::display_decode_error(input.path.as_deref(), &err);
+            succeeded = false;
+        }).ok()
+    }).collect();
+
+    (decodings, succeeded)
+}
+
+/// Exit with an error status if any input could not be decoded.
+fn exit_if_failed(succeeded: bool) {
+    if !succeeded {
+        process::exit(DECODE_FAILURE);
+    }
+}
@@ -0,0 +1,146 @@
+//! Detecting and repairing mojibake: text that was decoded with the wrong encoding.
+//!
+//! A common example is UTF-8 text that was decoded as windows-1252, and then encoded again,
+//! so that `é` turns into `Ã©`. Mojibake can be repaired by encoding the text with the
+//! encoding that was wrongly used, and then decoding it with the right one.
+//! ```
+//! let bytes = "CafÃ©".as_bytes();
+//! let string = string_inspector::DecodedString::decode(bytes, encoding::all::UTF_8).unwrap();
+//! let repairs = string_inspector::mojibake::analyse(&string);
+//!
+//! assert_eq!("Café", repairs[0].repaired.to_string());
+//! assert_eq!("windows-1252", repairs[0].misread_as.name());
+//! ```
+use std::fmt;
+use encoding::all::{ISO_8859_1, MAC_ROMAN, UTF_8, WINDOWS_1252};
+use encoding::types::EncodingRef;
+use encoding::EncoderTrap;
+use crate::cp437::IBM437;
+use crate::decoding::{Atom, DecodedString};
+use crate::detection;
+
+/// Text is sometimes misread more than once, but rarely more than this.
+const MAX_ROUNDS: usize = 3;
+
+/// The encodings most often mixed up with each other, in order of preference.
+fn common_encodings() -> [EncodingRef; 5] {
+    [UTF_8, WINDOWS_1252, ISO_8859_1, IBM437, MAC_ROMAN]
+}
+
+/// A possible explanation for some mojibake, and the text it would have been originally.
+pub struct Repair {
+    /// The encoding the text was really in.
+    pub original: EncodingRef,
+    /// The encoding the text was mistakenly decoded with.
+    pub misread_as: EncodingRef,
+    /// How many times the text was misread.
+    pub rounds: usize,
+    /// The text with the mistake undone.
+    pub repaired: DecodedString,
+    /// How much the repaired text looks like real text, between 0 and 1.
+    pub score: f32
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} misread as {}", self.original.name(), self.misread_as.name())?;
+        if self.rounds > 1 {
+            write!(f, " {} times", self.rounds)?;
+        }
+        Ok(())
+    }
+}
+
+/// Try undoing every likely mix up of encodings, and return the ones that make the text look better.
+///
+/// The result is sorted with the best explanation first, and is empty if the text doesn't look like mojibake.
+pub fn analyse(decoding: &DecodedString) -> Vec<Repair> {
+    let score_before = detection::text_score(decoding);
+    let mut repairs = Vec::new();
+
+    for &original in common_encodings().iter() {
+        for &misread_as in common_encodings().iter() {
+            if original.name() == misread_as.name() {
+                continue;
+            }
+
+            if let Some(repair) = repair(decoding, original, misread_as) {
+                if repair.score > score_before {
+                    repairs.push(repair);
+                }
+            }
+        }
+    }
+
+    // Sorting is stable, so ties are broken by the order of common_encodings()
+    repairs.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    repairs
+}
+
+/// Undo a particular mix up as many times as it keeps improving the text.
+fn repair(decoding: &DecodedString, original: EncodingRef, misread_as: EncodingRef) -> Option<Repair> {
+    let mut best: Option<Repair> = None;
+    let mut text = decoding.to_string();
+    let mut score = detection::text_score(decoding);
+
+    for rounds in 1..=MAX_ROUNDS {
+        let bytes = match misread_as.encode(&text, EncoderTrap::Strict) {
+            Ok(bytes) => bytes,
+            Err(_) => break
+        };
+
+        let repaired = match DecodedString::decode(&bytes, original) {
+            Ok(repaired) => repaired,
+            Err(_) => break
+        };
+
+        let repaired_text = repaired.to_string();
+        let repaired_score = detection::text_score(&repaired);
+        if repaired_text == text || repaired_score < score || !repaired.atoms.iter().all(Atom::is_valid) {
+            break;
+        }
+
+        text = repaired_text;
+        score = repaired_score;
+        best = Some(Repair { original, misread_as, rounds, repaired, score });
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyse_text(text: &str) -> Vec<Repair> {
+        let decoding = DecodedString::decode(text.as_bytes(), UTF_8).unwrap();
+        analyse(&decoding)
+    }
+
+    #[test]
+    fn correct_text_is_not_mojibake() {
+        assert!(analyse_text("Café crème").is_empty());
+        assert!(analyse_text("plain ascii").is_empty());
+    }
+
+    #[test]
+    fn utf8_misread_as_windows_1252() {
+        let repairs = analyse_text("CafÃ© crÃ¨me");
+        assert_eq!(repairs[0].to_string(), "utf-8 misread as windows-1252");
+        assert_eq!(repairs[0].repaired.to_string(), "Café crème");
+    }
+
+    #[test]
+    fn utf8_misread_twice() {
+        let repairs = analyse_text("CafÃƒÂ©");
+        assert_eq!(repairs[0].rounds, 2);
+        assert_eq!(repairs[0].repaired.to_string(), "Café");
+    }
+
+    #[test]
+    fn utf8_misread_as_cp437() {
+        let repairs = analyse_text("Caf├⌐");
+        assert_eq!(repairs[0].to_string(), "utf-8 misread as ibm437");
+        assert_eq!(repairs[0].repaired.to_string(), "Café");
+    }
+}
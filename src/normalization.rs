@@ -0,0 +1,150 @@
+//! Checking whether a string is in each of the unicode normalization forms.
+//!
+//! The same text can often be written with different sequences of code points. For example,
+//! `é` can be the single code point U+00E9 or an `e` followed by a combining acute accent (U+0301).
+//! Normalization converts text to one particular representation, so that strings that look the same
+//! compare equal.
+//! ```
+//! use string_inspector::normalization::{self, Form};
+//!
+//! let bytes = "e\u{301}".as_bytes();
+//! let string = string_inspector::DecodedString::decode(bytes, encoding::all::UTF_8).unwrap();
+//! let report = normalization::check(&string, Form::Nfc);
+//!
+//! assert!(!report.is_normalized());
+//! assert_eq!(vec![0, 1], report.changed);
+//! ```
+use std::fmt;
+use encoding::EncoderTrap;
+use unicode_normalization::UnicodeNormalization;
+use crate::decoding::DecodedString;
+
+/// One of the four unicode normalization forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    /// Canonical composition.
+    Nfc,
+    /// Canonical decomposition.
+    Nfd,
+    /// Compatibility composition.
+    Nfkc,
+    /// Compatibility decomposition.
+    Nfkd
+}
+
+impl Form {
+    pub const ALL: [Form; 4] = [Form::Nfc, Form::Nfd, Form::Nfkc, Form::Nfkd];
+
+    /// Look up a form by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Form> {
+        Form::ALL.iter().copied().find(|form| form.to_string().eq_ignore_ascii_case(name))
+    }
+
+    /// Convert some text to this form.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Form::Nfc => text.nfc().collect(),
+            Form::Nfd => text.nfd().collect(),
+            Form::Nfkc => text.nfkc().collect(),
+            Form::Nfkd => text.nfkd().collect()
+        }
+    }
+}
+
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Form::Nfc => "NFC",
+            Form::Nfd => "NFD",
+            Form::Nfkc => "NFKC",
+            Form::Nfkd => "NFKD"
+        };
+        f.pad(name)
+    }
+}
+
+/// How a decoded string changes when it is normalized.
+pub struct Report {
+    pub form: Form,
+    /// The indexes of the atoms that change.
+    pub changed: Vec<usize>,
+    /// The normalized text.
+    pub normalized: String
+}
+
+impl Report {
+    /// Whether the string is already in this form.
+    pub fn is_normalized(&self) -> bool {
+        self.changed.is_empty()
+    }
+}
+
+/// Normalize a decoded string, and work out which atoms change.
+///
+/// Normalization can reorder and combine neighbouring code points, so changes are tracked
+/// per grapheme cluster: if a cluster changes, all of its atoms are counted as changed.
+pub fn check(decoding: &DecodedString, form: Form) -> Report {
+    let mut changed = Vec::new();
+    let mut normalized = String::new();
+
+    for grapheme in decoding.graphemes() {
+        let text = grapheme.to_string();
+        let normalized_text = form.apply(&text);
+        if normalized_text != text {
+            changed.extend(grapheme.atoms.iter().map(|atom| atom.index()));
+        }
+        normalized.push_str(&normalized_text);
+    }
+
+    Report { form, changed, normalized }
+}
+
+/// Check a decoded string against every normalization form.
+pub fn analyse(decoding: &DecodedString) -> Vec<Report> {
+    Form::ALL.iter().map(|&form| check(decoding, form)).collect()
+}
+
+/// Normalize a decoded string, and encode the result with the same encoding so its bytes can be compared.
+///
+/// Returns `None` if the normalized text can't be represented in the encoding.
+pub fn normalize(decoding: &DecodedString, form: Form) -> Option<DecodedString> {
+    let normalized = form.apply(&decoding.to_string());
+    let bytes = decoding.encoding.encode(&normalized, EncoderTrap::Strict).ok()?;
+    DecodedString::decode(&bytes, decoding.encoding).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{UTF_8, WINDOWS_1252};
+
+    fn changed(text: &str) -> Vec<(Form, Vec<usize>)> {
+        let decoding = DecodedString::decode(text.as_bytes(), UTF_8).unwrap();
+        analyse(&decoding).into_iter().map(|report| (report.form, report.changed)).collect()
+    }
+
+    #[test]
+    fn ascii_is_always_normalized() {
+        assert!(changed("hello").iter().all(|(_, changed)| changed.is_empty()));
+    }
+
+    #[test]
+    fn composed_and_decomposed_accents() {
+        assert_eq!(changed("aé"), vec![(Form::Nfc, vec![]), (Form::Nfd, vec![1]), (Form::Nfkc, vec![]), (Form::Nfkd, vec![1])]);
+        assert_eq!(changed("ae\u{301}"), vec![(Form::Nfc, vec![1, 2]), (Form::Nfd, vec![]), (Form::Nfkc, vec![1, 2]), (Form::Nfkd, vec![])]);
+    }
+
+    #[test]
+    fn compatibility_characters() {
+        assert_eq!(changed("ﬁ"), vec![(Form::Nfc, vec![]), (Form::Nfd, vec![]), (Form::Nfkc, vec![0]), (Form::Nfkd, vec![0])]);
+    }
+
+    #[test]
+    fn normalized_bytes_use_the_same_encoding() {
+        let decoding = DecodedString::decode("é".as_bytes(), UTF_8).unwrap();
+        assert_eq!(normalize(&decoding, Form::Nfd).unwrap().to_string(), "e\u{301}");
+
+        let decoding = DecodedString::decode(&[0xe9], WINDOWS_1252).unwrap();
+        assert!(normalize(&decoding, Form::Nfd).is_none());
+    }
+}
@@ -0,0 +1,337 @@
+//! Turning atoms into text, lined up in rows of bytes and characters.
+//!
+//! A [Renderer](trait.Renderer.html) decides how each atom looks and how wide it is.
+//! [TerminalRenderer](struct.TerminalRenderer.html) produces the coloured output of the command line tool,
+//! and can be configured with [RenderOptions](struct.RenderOptions.html).
+//! ```
+//! use string_inspector::render::{Radix, RenderOptions, Renderer, TerminalRenderer};
+//!
+//! let string = string_inspector::DecodedString::decode("é!".as_bytes(), encoding::all::UTF_8).unwrap();
+//! let renderer = TerminalRenderer::new(RenderOptions { colors: None, byte_radix: Radix::Decimal, ..RenderOptions::default() });
+//!
+//! assert_eq!("195 169  33 ", renderer.bytes_row(&string.atoms));
+//! assert_eq!("e9      !   ", renderer.characters_row(&string.atoms));
+//! ```
+use colored::*;
+use std::cmp;
+use std::slice;
+use crate::decoding::{Atom, DecodedString, Replacement};
+use crate::graphemes::Grapheme;
+
+/// The number of columns for a surrogate that escapes a byte, like "dcff", and a space after it.
+const ESCAPED_BYTE_WIDTH: usize = 5;
+
+/// How to write numbers, such as bytes and offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    Decimal,
+    /// Hexadecimal with lowercase digits.
+    #[default]
+    Hex,
+    /// Hexadecimal with uppercase digits.
+    UpperHex,
+    Octal,
+    Binary
+}
+
+impl Radix {
+    /// Look up a radix by the name used on the command line, like "hex" or "upper-hex".
+    pub fn from_name(name: &str) -> Option<Radix> {
+        match name {
+            "decimal" => Some(Radix::Decimal),
+            "hex" => Some(Radix::Hex),
+            "upper-hex" => Some(Radix::UpperHex),
+            "octal" => Some(Radix::Octal),
+            "binary" => Some(Radix::Binary),
+            _ => None
+        }
+    }
+
+    /// Format a number, padded with zeros to at least `width` digits.
+    ///
+    /// Decimal numbers are padded with spaces instead, because a leading zero usually means octal.
+    pub fn format(self, number: usize, width: usize) -> String {
+        match self {
+            Radix::Decimal => format!("{:>width$}", number, width = width),
+            Radix::Hex => format!("{:0width$x}", number, width = width),
+            Radix::UpperHex => format!("{:0width$X}", number, width = width),
+            Radix::Octal => format!("{:0width$o}", number, width = width),
+            Radix::Binary => format!("{:0width$b}", number, width = width)
+        }
+    }
+
+    /// The number of digits needed to write any byte.
+    pub fn byte_digits(self) -> usize {
+        self.format(u8::MAX as usize, 0).len()
+    }
+}
+
+/// Settings for a [TerminalRenderer](struct.TerminalRenderer.html).
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Colours to alternate between for neighbouring atoms, or `None` for plain text.
+    pub colors: Option<(Color, Color)>,
+    /// How to write each byte.
+    pub byte_radix: Radix,
+    /// Written after each byte.
+    pub separator: String,
+    /// The number of columns for each byte, including the separator.
+    /// If `None`, this is just wide enough for the radix and the separator.
+    pub byte_width: Option<usize>
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            colors: Some((Color::Green, Color::Blue)),
+            byte_radix: Radix::Hex,
+            separator: " ".to_owned(),
+            byte_width: None
+        }
+    }
+}
+
+impl RenderOptions {
+    /// The number of columns used for each byte.
+    pub fn byte_width(&self) -> usize {
+        self.byte_width.unwrap_or_else(|| self.byte_radix.byte_digits() + self.separator.chars().count())
+    }
+}
+
+/// Formats atoms so that each character lines up with its bytes.
+///
+/// Implementations only need to format single atoms; the rows are built from them.
+pub trait Renderer {
+    /// Format the bytes of an atom.
+    fn render_bytes(&self, atom: &Atom) -> String;
+
+    /// Format the character of an atom, taking up the same width as its bytes.
+    fn render_character(&self, atom: &Atom) -> String;
+
+    /// Format a bracket spanning the same width as the bytes of a grapheme cluster.
+    fn render_grapheme(&self, grapheme: &Grapheme) -> String;
+
+    /// The number of columns an atom takes up.
+    fn width(&self, atom: &Atom) -> usize;
+
+    /// Style the output for some atoms at `position` within a row, such as by colouring it.
+    ///
+    /// The atoms are a single atom in the bytes and characters rows, and a whole grapheme in the graphemes row.
+    /// By default the output is left alone.
+    fn decorate(&self, _position: usize, _atoms: &[Atom], output: String) -> String {
+        output
+    }
+
+    /// Format the bytes of each atom.
+    fn bytes_row(&self, atoms: &[Atom]) -> String {
+        atoms.iter().enumerate()
+            .map(|(position, atom)| self.decorate(position, slice::from_ref(atom), self.render_bytes(atom)))
+            .collect()
+    }
+
+    /// Format the character of each atom, lined up with [bytes_row](#method.bytes_row).
+    fn characters_row(&self, atoms: &[Atom]) -> String {
+        atoms.iter().enumerate()
+            .map(|(position, atom)| self.decorate(position, slice::from_ref(atom), self.render_character(atom)))
+            .collect()
+    }
+
+    /// Format a bracket under each grapheme cluster, lined up with [bytes_row](#method.bytes_row).
+    fn graphemes_row(&self, decoding: &DecodedString) -> String {
+        decoding.graphemes().iter().enumerate()
+            .map(|(position, grapheme)| self.decorate(position, grapheme.atoms, self.render_grapheme(grapheme)))
+            .collect()
+    }
+
+    /// Split a decoding into lines whose rows fit within `max_line_width` columns.
+    fn wrap_lines(&self, decoding: &DecodedString, max_line_width: usize) -> Vec<DecodedString> {
+        decoding.wrap_lines_with(max_line_width, |atom| self.width(atom))
+    }
+}
+
+/// Renders atoms as text for a terminal, alternating colours between neighbouring atoms.
+///
+/// ASCII characters are rendered normally.
+/// Tabs, carriage returns and newlines are represented as escape sequences.
+/// All other characters are rendered as their unicode codepoints in hex.
+/// Byte order marks are rendered as "BOM".
+/// Invalid code units are rendered as unicode replacement characters (U+FFFD), a dot if the decoder
+/// outputs nothing for them, or the code points of their escaped surrogates.
+///
+/// Each atom is as wide as its bytes, unless its character needs more room, in which case the bytes are padded to match.
+/// Characters that the decoder output along with an earlier one have no bytes of their own, and get an empty byte slot.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalRenderer {
+    pub options: RenderOptions
+}
+
+impl TerminalRenderer {
+    pub fn new(options: RenderOptions) -> Self {
+        TerminalRenderer { options }
+    }
+
+    /// The character of an atom without any padding, and the number of columns it takes up.
+    fn character_text(&self, atom: &Atom) -> (String, usize) {
+        let text = match atom {
+            Atom::Character(c) => match c.character {
+                '\t' | '\r' | '\n' => c.character.escape_default().to_string(),
+                '\u{20}'..='\u{7e}' => c.character.to_string(),
+                _ => {
+                    // Code points are always written in hex, but follow the case of the bytes
+                    let radix = if self.options.byte_radix == Radix::UpperHex { Radix::UpperHex } else { Radix::Hex };
+                    radix.format(c.character as u32 as usize, 2)
+                }
+            },
+            Atom::ByteOrderMark(_) => "BOM".to_owned(),
+            _ => match atom.replacement() {
+                Some(Replacement::Omitted) => "·".to_owned(),
+                Some(Replacement::SurrogateEscape) => {
+                    // Each surrogate lines up with the byte it escapes
+                    let slot = self.escaped_byte_width();
+                    let surrogates: String = atom.to_bytes().iter().map(|&byte| format!("{:<slot$x}", 0xdc00 + u32::from(byte), slot = slot)).collect();
+                    surrogates.trim_end().to_owned()
+                }
+                // U+FFFD has an ambiguous width, and takes up two columns in many terminals.
+                _ => return ('\u{FFFD}'.to_string(), 2)
+            }
+        };
+
+        let columns = text.chars().count();
+        (text, columns)
+    }
+
+    /// The number of columns for each byte of an atom that is escaped as surrogates,
+    /// which is wide enough for both the byte and a surrogate like "dcff".
+    fn escaped_byte_width(&self) -> usize {
+        cmp::max(self.options.byte_width(), ESCAPED_BYTE_WIDTH)
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn render_bytes(&self, atom: &Atom) -> String {
+        let digits = self.options.byte_radix.byte_digits();
+        let byte_width = match atom.replacement() {
+            Some(Replacement::SurrogateEscape) => self.escaped_byte_width(),
+            _ => self.options.byte_width()
+        };
+
+        let bytes: String = atom.to_bytes().iter().map(|&byte| {
+            let formatted = format!("{}{}", self.options.byte_radix.format(byte as usize, digits), self.options.separator);
+            format!("{:width$}", formatted, width = byte_width)
+        }).collect();
+        format!("{:width$}", bytes, width = self.width(atom))
+    }
+
+    fn render_character(&self, atom: &Atom) -> String {
+        let (text, columns) = self.character_text(atom);
+        format!("{}{}", text, " ".repeat(self.width(atom) - columns))
+    }
+
+    fn render_grapheme(&self, grapheme: &Grapheme) -> String {
+        let width: usize = grapheme.atoms.iter().map(|atom| self.width(atom)).sum();
+        // Cells narrower than the bracket and its trailing space get as much of the bracket as fits
+        let line = "─".repeat(width.saturating_sub(3));
+        format!("└{}┘ ", line).chars().take(width).collect()
+    }
+
+    fn width(&self, atom: &Atom) -> usize {
+        // Leave at least one space after the character, so that it doesn't run into the next one
+        let (_, columns) = self.character_text(atom);
+        let bytes = cmp::max(atom.byte_range().len(), 1);
+        cmp::max(bytes * self.options.byte_width(), columns + 1)
+    }
+
+    fn decorate(&self, position: usize, _atoms: &[Atom], output: String) -> String {
+        match self.options.colors {
+            Some((even, odd)) => output.color(if position % 2 == 0 { even } else { odd }).to_string(),
+            None => output
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{UTF_8, UTF_16LE};
+    use crate::decoding::{DecodeOptions, ErrorHandling};
+
+    fn plain(options: RenderOptions) -> TerminalRenderer {
+        TerminalRenderer::new(RenderOptions { colors: None, ..options })
+    }
+
+    #[test]
+    fn separators_and_widths() {
+        let decoding = DecodedString::decode(b"a\n", UTF_8).unwrap();
+        let renderer = plain(RenderOptions { separator: "|".to_owned(), byte_width: Some(4), ..RenderOptions::default() });
+
+        assert_eq!(renderer.bytes_row(&decoding.atoms), "61| 0a| ");
+        assert_eq!(renderer.characters_row(&decoding.atoms), "a   \\n  ");
+    }
+
+    #[test]
+    fn escapes_fill_multi_byte_code_units() {
+        let decoding = DecodedString::decode(&[0x0a, 0x00], UTF_16LE).unwrap();
+        let renderer = plain(RenderOptions::default());
+
+        assert_eq!(renderer.characters_row(&decoding.atoms), "\\n    ");
+        assert_eq!(renderer.characters_row(&decoding.atoms).len(), renderer.bytes_row(&decoding.atoms).len());
+    }
+
+    #[test]
+    fn escaped_surrogates_line_up_with_their_bytes() {
+        let options = DecodeOptions { errors: ErrorHandling::SurrogateEscape, ..DecodeOptions::default() };
+        let renderer = plain(RenderOptions::default());
+
+        let decoding = DecodedString::decode_with(b"a\xff\xfeb\xc3", UTF_8, &options).unwrap();
+        assert_eq!(renderer.bytes_row(&decoding.atoms), "61 ff   fe   62 c3   ");
+        assert_eq!(renderer.characters_row(&decoding.atoms), "a  dcff dcfe b  dcc3 ");
+        assert_eq!(renderer.wrap_lines(&decoding, 16).len(), 2);
+
+        // An unpaired surrogate in UTF-16 is one atom, but each of its bytes is escaped separately
+        let decoding = DecodedString::decode_with(&[0x80, 0xd8], UTF_16LE, &options).unwrap();
+        assert_eq!(renderer.bytes_row(&decoding.atoms), "80   d8   ");
+        assert_eq!(renderer.characters_row(&decoding.atoms), "dc80 dcd8 ");
+    }
+
+    #[test]
+    fn every_radix_lines_up() {
+        let decoding = DecodedString::decode("é".as_bytes(), UTF_8).unwrap();
+        let rows = |byte_radix| {
+            let renderer = plain(RenderOptions { byte_radix, ..RenderOptions::default() });
+            (renderer.bytes_row(&decoding.atoms), renderer.characters_row(&decoding.atoms))
+        };
+
+        assert_eq!(rows(Radix::UpperHex), ("C3 A9 ".to_owned(), "E9    ".to_owned()));
+        assert_eq!(rows(Radix::Octal), ("303 251 ".to_owned(), "e9      ".to_owned()));
+        assert_eq!(rows(Radix::Binary), ("11000011 10101001 ".to_owned(), format!("{:18}", "e9 ")));
+    }
+
+    #[test]
+    fn grapheme_brackets_fit_their_cells() {
+        let decoding = DecodedString::decode(b"ab", UTF_8).unwrap();
+        let rows = |options| {
+            let renderer = plain(options);
+            (renderer.bytes_row(&decoding.atoms), renderer.graphemes_row(&decoding))
+        };
+
+        assert_eq!(rows(RenderOptions::default()), ("61 62 ".to_owned(), "└┘ └┘ ".to_owned()));
+        assert_eq!(rows(RenderOptions { byte_radix: Radix::Octal, ..RenderOptions::default() }), ("141 142 ".to_owned(), "└─┘ └─┘ ".to_owned()));
+        assert_eq!(
+            rows(RenderOptions { byte_radix: Radix::Binary, ..RenderOptions::default() }),
+            ("01100001 01100010 ".to_owned(), "└──────┘ └──────┘ ".to_owned())
+        );
+        assert_eq!(rows(RenderOptions { separator: String::new(), ..RenderOptions::default() }), ("6162".to_owned(), "└┘└┘".to_owned()));
+    }
+
+    #[test]
+    fn wider_bytes_wrap_sooner() {
+        let decoding = DecodedString::decode(b"abcd", UTF_8).unwrap();
+        let hex = plain(RenderOptions::default());
+        let decimal = plain(RenderOptions { byte_radix: Radix::Decimal, ..RenderOptions::default() });
+
+        assert_eq!(hex.wrap_lines(&decoding, 12).len(), 1);
+        assert_eq!(decimal.wrap_lines(&decoding, 12).len(), 2);
+        assert_eq!(decimal.bytes_row(&decoding.atoms), " 97  98  99 100 ");
+        assert_eq!(decimal.graphemes_row(&decoding), "└─┘ └─┘ └─┘ └─┘ ");
+    }
+}
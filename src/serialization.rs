@@ -0,0 +1,151 @@
+//! Serializing decoded strings with [serde](https://serde.rs/), when the `serde` feature is enabled.
+//!
+//! Each atom is serialized as an object with its kind, bytes, code point, position and validity,
+//! plus the reason, explanation and replacement if it is invalid, and each decoded string has its diagnostics and a summary of how many atoms of each kind it contains.
+//! ```
+//! let string = string_inspector::DecodedString::decode(&[0x41, 0xff], encoding::all::UTF_8).unwrap();
+//! let json = serde_json::to_value(&string).unwrap();
+//!
+//! assert_eq!("utf-8", json["encoding"]);
+//! assert_eq!(65, json["atoms"][0]["codepoint"]);
+//! assert_eq!(false, json["atoms"][1]["valid"]);
+//! assert_eq!("invalid byte", json["atoms"][1]["reason"]);
+//! assert_eq!(1, json["summary"]["invalid"]);
+//! ```
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use crate::decoding::{Atom, DecodedCharacter, DecodedString, Diagnostic, Replacement};
+
+impl Serialize for DecodedCharacter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("DecodedCharacter", 4)?;
+        state.serialize_field("character", &self.character)?;
+        state.serialize_field("bytes", &self.bytes)?;
+        state.serialize_field("offset", &self.offset)?;
+        state.serialize_field("index", &self.index)?;
+        state.end()
+    }
+}
+
+impl Atom {
+    /// A name for the kind of atom, for use in serialized output.
+    fn kind(&self) -> &'static str {
+        match self {
+            Atom::Character(_) => "character",
+            Atom::ByteOrderMark(_) => "byte_order_mark",
+            Atom::InvalidCodeUnit(_) => "invalid_code_unit",
+            Atom::UnpairedSurrogate(_) => "unpaired_surrogate",
+            Atom::SurrogateCodePoint(_) => "surrogate_code_point",
+            Atom::OutOfRange(_) => "out_of_range"
+        }
+    }
+
+    /// The code points a decoder outputs in place of the atom, if it is invalid.
+    ///
+    /// These are numbers rather than a string, because escaped bytes are lone surrogates, which strings can't hold.
+    fn replacement_code_points(&self) -> Option<Vec<u32>> {
+        self.replacement().map(|replacement| match replacement {
+            Replacement::ReplacementCharacter => vec![0xfffd],
+            Replacement::Omitted => Vec::new(),
+            Replacement::SurrogateEscape => self.to_bytes().iter().map(|&byte| 0xdc00 + u32::from(byte)).collect()
+        })
+    }
+}
+
+impl Serialize for Atom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let character = if self.is_valid() { Some(self.to_char()) } else { None };
+
+        let mut state = serializer.serialize_struct("Atom", 10)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("bytes", &self.to_bytes())?;
+        state.serialize_field("codepoint", &character.map(u32::from))?;
+        state.serialize_field("character", &character)?;
+        state.serialize_field("offset", &self.offset())?;
+        state.serialize_field("index", &self.index())?;
+        state.serialize_field("valid", &self.is_valid())?;
+        state.serialize_field("reason", &self.reason().map(|reason| reason.to_string()))?;
+        state.serialize_field("explanation", &self.explanation())?;
+        state.serialize_field("replacement", &self.replacement_code_points())?;
+        state.end()
+    }
+}
+
+impl Serialize for Diagnostic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Diagnostic", 2)?;
+        state.serialize_field("offset", &self.offset)?;
+        state.serialize_field("cause", &self.cause)?;
+        state.end()
+    }
+}
+
+/// How many atoms of each kind a decoded string contains.
+struct Summary {
+    atoms: usize,
+    characters: usize,
+    invalid: usize,
+    bytes: usize
+}
+
+impl Serialize for Summary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Summary", 4)?;
+        state.serialize_field("atoms", &self.atoms)?;
+        state.serialize_field("characters", &self.characters)?;
+        state.serialize_field("invalid", &self.invalid)?;
+        state.serialize_field("bytes", &self.bytes)?;
+        state.end()
+    }
+}
+
+impl Serialize for DecodedString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let invalid = self.atoms.iter().filter(|atom| !atom.is_valid()).count();
+        let summary = Summary {
+            atoms: self.atoms.len(),
+            characters: self.atoms.len() - invalid,
+            invalid,
+            bytes: self.atoms.last().map_or(0, |atom| atom.byte_range().end)
+        };
+
+        let mut state = serializer.serialize_struct("DecodedString", 4)?;
+        state.serialize_field("encoding", self.encoding.name())?;
+        state.serialize_field("atoms", &self.atoms)?;
+        state.serialize_field("diagnostics", &self.diagnostics)?;
+        state.serialize_field("summary", &summary)?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding::all::{UTF_8, UTF_16LE};
+    use crate::decoding::{DecodeOptions, ErrorHandling};
+    use serde_json::json;
+
+    #[test]
+    fn atoms_of_every_kind() {
+        let decoding = DecodedString::decode(&[0xff, 0xfe, 0xe9, 0x00, 0x00, 0xd8], UTF_16LE).unwrap();
+        let json = serde_json::to_value(&decoding).unwrap();
+
+        assert_eq!(json["atoms"][0], json!({"kind": "byte_order_mark", "bytes": [0xff, 0xfe], "codepoint": 0xfeff, "character": "\u{feff}", "offset": 0, "index": 0, "valid": true, "reason": null, "explanation": null, "replacement": null}));
+        assert_eq!(json["atoms"][1], json!({"kind": "character", "bytes": [0xe9, 0x00], "codepoint": 0xe9, "character": "é", "offset": 2, "index": 1, "valid": true, "reason": null, "explanation": null, "replacement": null}));
+        assert_eq!(json["atoms"][2], json!({"kind": "unpaired_surrogate", "bytes": [0x00, 0xd8], "codepoint": null, "character": null, "offset": 4, "index": 2, "valid": false, "reason": "unpaired surrogate", "explanation": "expected a low surrogate after high surrogate d800, found none", "replacement": [0xfffd]}));
+        assert_eq!(json["diagnostics"], json!([{"offset": 4, "cause": "incomplete sequence"}]));
+        assert_eq!(json["summary"], json!({"atoms": 3, "characters": 2, "invalid": 1, "bytes": 6}));
+    }
+
+    #[test]
+    fn replacements_follow_the_error_handling() {
+        let replacements = |errors| {
+            let decoding = DecodedString::decode_with(&[0x41, 0xe2, 0x82], UTF_8, &DecodeOptions { errors, ..DecodeOptions::default() }).unwrap();
+            let json = serde_json::to_value(&decoding).unwrap();
+            json["atoms"].as_array().unwrap().iter().map(|atom| atom["replacement"].clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(replacements(ErrorHandling::Replace), vec![json!(null), json!([0xfffd]), json!([0xfffd])]);
+        assert_eq!(replacements(ErrorHandling::Ignore), vec![json!(null), json!([]), json!([])]);
+        assert_eq!(replacements(ErrorHandling::SurrogateEscape), vec![json!(null), json!([0xdce2]), json!([0xdc82])]);
+    }
+}
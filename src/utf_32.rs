@@ -0,0 +1,176 @@
+//! UTF-32 encodings, which are not provided by the encoding crate.
+//!
+//! These implement [Encoding](https://docs.rs/encoding/0.2/encoding/types/trait.Encoding.html),
+//! so they can be used anywhere an `EncodingRef` is expected.
+//! ```
+//! let bytes = [0xa9, 0xf4, 0x01, 0x00];
+//! let string = string_inspector::DecodedString::decode(&bytes, string_inspector::utf_32::UTF_32LE).unwrap();
+//!
+//! assert_eq!("💩", string.to_string());
+//! ```
+use encoding::types::{ByteWriter, CodecError, Encoding, RawDecoder, RawEncoder, StringWriter};
+use crate::decoding::Endianness;
+
+/// UTF-32 with the least significant byte first.
+pub const UTF_32LE: &UTF32Encoding = &UTF32Encoding { endianness: Endianness::Little };
+
+/// UTF-32 with the most significant byte first.
+pub const UTF_32BE: &UTF32Encoding = &UTF32Encoding { endianness: Endianness::Big };
+
+/// An encoding where every character is a single 4 byte code unit.
+#[derive(Clone, Copy)]
+pub struct UTF32Encoding {
+    endianness: Endianness
+}
+
+impl Encoding for UTF32Encoding {
+    fn name(&self) -> &'static str {
+        match self.endianness {
+            Endianness::Little => "utf-32le",
+            Endianness::Big => "utf-32be"
+        }
+    }
+
+    fn raw_encoder(&self) -> Box<dyn RawEncoder> {
+        Box::new(UTF32Encoder { endianness: self.endianness })
+    }
+
+    fn raw_decoder(&self) -> Box<dyn RawDecoder> {
+        Box::new(UTF32Decoder { endianness: self.endianness, buffer: [0; 4], buffered: 0 })
+    }
+}
+
+/// Encodes each character as a 4 byte code unit.
+struct UTF32Encoder {
+    endianness: Endianness
+}
+
+impl RawEncoder for UTF32Encoder {
+    fn from_self(&self) -> Box<dyn RawEncoder> {
+        Box::new(UTF32Encoder { endianness: self.endianness })
+    }
+
+    fn raw_feed(&mut self, input: &str, output: &mut dyn ByteWriter) -> (usize, Option<CodecError>) {
+        output.writer_hint(input.len() * 4);
+        for character in input.chars() {
+            let value = character as u32;
+            match self.endianness {
+                Endianness::Little => output.write_bytes(&value.to_le_bytes()),
+                Endianness::Big => output.write_bytes(&value.to_be_bytes())
+            }
+        }
+        (input.len(), None)
+    }
+
+    fn raw_finish(&mut self, _output: &mut dyn ByteWriter) -> Option<CodecError> {
+        None
+    }
+}
+
+/// Decodes 4 byte code units, holding on to any partial code unit until the next call.
+struct UTF32Decoder {
+    endianness: Endianness,
+    buffer: [u8; 4],
+    buffered: usize
+}
+
+impl UTF32Decoder {
+    fn value(&self, bytes: [u8; 4]) -> u32 {
+        match self.endianness {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes)
+        }
+    }
+}
+
+/// Explain why a code unit is not a valid character.
+fn invalid_code_unit(value: u32, upto: usize) -> CodecError {
+    let cause = if (0xd800..=0xdfff).contains(&value) {
+        "surrogate code point"
+    } else {
+        "code point out of range"
+    };
+    CodecError { upto: upto as isize, cause: cause.into() }
+}
+
+impl RawDecoder for UTF32Decoder {
+    fn from_self(&self) -> Box<dyn RawDecoder> {
+        Box::new(UTF32Decoder { endianness: self.endianness, buffer: [0; 4], buffered: 0 })
+    }
+
+    fn raw_feed(&mut self, input: &[u8], output: &mut dyn StringWriter) -> (usize, Option<CodecError>) {
+        output.writer_hint(input.len() / 4);
+
+        let mut i = 0;
+        let mut processed = 0;
+
+        // Complete any code unit left over from the previous call
+        if self.buffered > 0 {
+            while self.buffered < 4 && i < input.len() {
+                self.buffer[self.buffered] = input[i];
+                self.buffered += 1;
+                i += 1;
+            }
+            if self.buffered < 4 {
+                return (0, None);
+            }
+
+            self.buffered = 0;
+            let value = self.value(self.buffer);
+            match std::char::from_u32(value) {
+                Some(character) => {
+                    output.write_char(character);
+                    processed = i;
+                }
+                None => return (processed, Some(invalid_code_unit(value, i)))
+            }
+        }
+
+        while i + 4 <= input.len() {
+            let value = self.value([input[i], input[i + 1], input[i + 2], input[i + 3]]);
+            i += 4;
+            match std::char::from_u32(value) {
+                Some(character) => {
+                    output.write_char(character);
+                    processed = i;
+                }
+                None => return (processed, Some(invalid_code_unit(value, i)))
+            }
+        }
+
+        self.buffered = input.len() - i;
+        self.buffer[..self.buffered].copy_from_slice(&input[i..]);
+        (processed, None)
+    }
+
+    fn raw_finish(&mut self, _output: &mut dyn StringWriter) -> Option<CodecError> {
+        if self.buffered > 0 {
+            self.buffered = 0;
+            Some(CodecError { upto: 0, cause: "incomplete sequence".into() })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_units_can_be_split_across_calls() {
+        let mut decoder = UTF_32BE.raw_decoder();
+        let mut output = String::new();
+
+        assert_eq!(decoder.raw_feed(&[0x00, 0x01], &mut output).0, 0);
+        assert_eq!(decoder.raw_feed(&[0xf4, 0xa9, 0x00], &mut output).0, 2);
+        assert_eq!(output, "💩");
+        assert!(decoder.raw_finish(&mut output).is_some());
+    }
+
+    #[test]
+    fn encodes_to_four_bytes_per_character() {
+        let bytes = UTF_32LE.encode("A💩", encoding::EncoderTrap::Strict).unwrap();
+        assert_eq!(bytes, vec![0x41, 0x00, 0x00, 0x00, 0xa9, 0xf4, 0x01, 0x00]);
+    }
+}